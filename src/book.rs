@@ -1,15 +1,18 @@
+use crate::bookcompiler::{
+    CompileJob, LatexOptions, Layout, Locale, ManuscriptOptions, Markup, Matter, OutputFormat,
+};
 use crate::error::MyError;
+use crate::template::Template;
 use crate::vcs::*;
 use crate::AppState;
-use actix_web::{
-    AsyncResponder, FutureResponse, HttpRequest, HttpResponse, Json, Responder, State,
-};
+use actix_web::{HttpRequest, HttpResponse, Json, Responder, State};
 use app_dirs::{AppDataType, AppInfo};
-use futures::Future;
+use git2::Oid;
 use sha1::Sha1;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 use std::io::prelude::*;
+use std::path::Component;
 use std::path::Path;
 use std::path::PathBuf;
 use walkdir::WalkDir;
@@ -19,23 +22,98 @@ pub struct Book {
     files: HashMap<String, File>,
     location: PathBuf,
     name: String,
+    meta: BookMeta,
     remotes: Vec<String>,
     branches: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-enum Genre {
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Genre {
     Fantasy,
     Fiction,
     Academic,
 }
 
-#[allow(unused)]
+const BOOK_META_VERSION: u32 = 1;
+
+/// Book level metadata stored in `.collabook/book.toml`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BookMeta {
+    #[serde(default = "book_meta_version")]
+    pub version: u32,
+    pub title: String,
+    pub subtitle: Option<String>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    pub genre: Option<Genre>,
+    pub language: Option<String>,
+    pub description: Option<String>,
+    pub isbn: Option<String>,
+    pub publisher: Option<String>,
+    pub rights: Option<String>,
+    /// Year of the copyright notice, the year of the compiled commit when not set
+    pub copyright_year: Option<i32>,
+    /// Written in the markup of the compile, like the about the author text
+    pub dedication: Option<String>,
+    pub about_author: Option<String>,
+}
+
+fn book_meta_version() -> u32 {
+    BOOK_META_VERSION
+}
+
+impl BookMeta {
+    pub fn new(title: &str) -> Self {
+        BookMeta {
+            version: BOOK_META_VERSION,
+            title: title.to_string(),
+            subtitle: None,
+            authors: Vec::new(),
+            genre: None,
+            language: None,
+            description: None,
+            isbn: None,
+            publisher: None,
+            rights: None,
+            copyright_year: None,
+            dedication: None,
+            about_author: None,
+        }
+    }
+
+    //books created before book.toml existed use the name of their folder as title
+    pub fn read(location: &Path) -> Result<Self, MyError> {
+        let path = location.join(".collabook/book.toml");
+        if !path.exists() {
+            let name = location
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or("Filename contains invalid utf-8")?;
+            return Ok(BookMeta::new(name));
+        }
+        let meta: BookMeta = toml::from_str(&fs::read_to_string(path)?)?;
+        if meta.version > BOOK_META_VERSION {
+            Err("Book was created by a newer version of Collabook")?
+        }
+        Ok(meta)
+    }
+
+    pub fn write(&self, location: &Path) -> Result<(), MyError> {
+        let contents = toml::to_string(self)?;
+        let mut file = fs::File::create(location.join(".collabook/book.toml"))?;
+        file.write_all(contents.as_bytes())?;
+        Ok(())
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct NewBookRequest<T: AsRef<Path>> {
     location: T,
     name: String,
     genre: Genre,
+    //name of a builtin or user template, defaults to the template of the genre
+    #[serde(default)]
+    template: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -50,11 +128,30 @@ struct File {
     is_research: bool,
     content: Option<String>,
     synopsis: String,
+    children: Vec<String>,
+}
+
+/// A file of the book as handed to the compiler.
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub id: String,
+    pub name: String,
+    pub content: String,
+    pub synopsis: String,
+}
+
+/// Sections which are next to each other in the same folder, the chapter takes its title from
+/// the folder.
+#[derive(Debug, Clone)]
+pub struct Chapter {
+    pub id: String,
+    pub title: String,
+    pub sections: Vec<Section>,
 }
 
 impl File {
     fn new(name: &str, rel_path: &str, parent: &str, is_folder: bool, is_research: bool) -> Self {
-        let id = sha1::Sha1::from(rel_path).digest().to_string();
+        let id = new_id();
         let content: Option<String>;
 
         if is_folder {
@@ -73,10 +170,11 @@ impl File {
             is_research,
             content,
             synopsis: "".to_owned(),
+            children: Vec::new(),
         }
     }
 
-    fn from_location<P: AsRef<Path>>(path: P, book: P) -> Result<Self, MyError> {
+    fn from_location<P: AsRef<Path>>(path: P, book: P, ids: &mut FileIds) -> Result<Self, MyError> {
         let name = &path
             .as_ref()
             .file_name()
@@ -86,14 +184,10 @@ impl File {
         let rel_path = path.as_ref().strip_prefix(&book)?;
 
         let parent_id = match rel_path.parent() {
-            Some(parent) => parent
-                .to_str()
-                .ok_or("Filename contains invalid utf-8")
-                .map(|par| Sha1::from(par).digest().to_string()),
-            None => Ok("0".to_string()),
-        }?;
-
-        let rel_path_str = rel_path.to_str().ok_or("Filename contains invalid utf-8")?;
+            Some(parent) => ids.id_for(parent)?,
+            None => "0".to_string(),
+        };
+
         let is_folder = path.as_ref().is_dir();
         let is_research = rel_path.to_string_lossy().contains("Research");
 
@@ -108,15 +202,13 @@ impl File {
             content = None
         }
 
-        //read synopsis
-        //TODO: should it be error if synopsis file is not found?
-        //
-        let rel_path_str2 = rel_path_str.replace("\\", "/"); //needed in windows as windows uses `\` instead of `/` on windows
-        let id = Sha1::from(rel_path_str2).digest().to_string();
-        //TODO: create synopsis file if not present
-        let mut syn_file = fs::File::open(&book.as_ref().join(".collabook/synopsis").join(&id))?;
+        //read synopsis, files added outside of collabook don't have one yet
+        let id = ids.id_for(rel_path)?;
+        let syn_path = book.as_ref().join(".collabook/synopsis").join(&id);
         let mut synopsis = String::new();
-        syn_file.read_to_string(&mut synopsis)?;
+        if syn_path.exists() {
+            fs::File::open(&syn_path)?.read_to_string(&mut synopsis)?;
+        }
 
         let f = File {
             id,
@@ -128,11 +220,265 @@ impl File {
             is_research,
             content,
             synopsis,
+            children: Vec::new(),
         };
         Ok(f)
     }
 }
 
+fn new_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+//relative paths are stored with `/` as separator on every platform
+fn rel_path_key(rel_path: &Path) -> Result<String, MyError> {
+    let rel_path_str = rel_path.to_str().ok_or("Filename contains invalid utf-8")?;
+    Ok(rel_path_str.replace("\\", "/")) //needed in windows as windows uses `\` instead of `/`
+}
+
+//books created before ids were stored used the sha1 of the relative path as id
+fn legacy_path_id(rel_path: &Path) -> Result<String, MyError> {
+    Ok(Sha1::from(rel_path_key(rel_path)?).digest().to_string())
+}
+
+const FILE_IDS_VERSION: u32 = 1;
+
+/// Ids of every file in the book, stored in `.collabook/ids.toml` so that an id stays the same
+/// when the file is renamed or moved. Maps id to relative path.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct FileIds {
+    version: u32,
+    paths: BTreeMap<String, String>,
+    /// Maps relative path to id, the other way round of `paths`
+    #[serde(skip)]
+    ids: HashMap<String, String>,
+    /// Whether an id was assigned or forgotten since the ids were read
+    #[serde(skip)]
+    changed: bool,
+}
+
+impl Default for FileIds {
+    fn default() -> Self {
+        FileIds {
+            version: FILE_IDS_VERSION,
+            paths: BTreeMap::new(),
+            ids: HashMap::new(),
+            changed: false,
+        }
+    }
+}
+
+impl FileIds {
+    fn from_files<'a, I: IntoIterator<Item = &'a File>>(files: I) -> Result<Self, MyError> {
+        let mut ids = FileIds::default();
+        for file in files {
+            ids.insert(file.id.clone(), rel_path_key(&file.rel_path)?);
+        }
+        Ok(ids)
+    }
+
+    fn index(&mut self) {
+        self.ids = self
+            .paths
+            .iter()
+            .map(|(id, path)| (path.clone(), id.clone()))
+            .collect();
+    }
+
+    fn insert(&mut self, id: String, path: String) {
+        if let Some(old) = self.paths.insert(id.clone(), path.clone()) {
+            self.ids.remove(&old);
+        }
+        self.ids.insert(path, id);
+        self.changed = true;
+    }
+
+    fn remove(&mut self, id: &str) {
+        if let Some(path) = self.paths.remove(id) {
+            self.ids.remove(&path);
+            self.changed = true;
+        }
+    }
+
+    //returns an empty map for books which don't have an ids file yet
+    fn read(location: &Path) -> Result<Self, MyError> {
+        let path = location.join(".collabook/ids.toml");
+        if !path.exists() {
+            return Ok(FileIds::default());
+        }
+        let mut ids: FileIds = toml::from_str(&fs::read_to_string(path)?)?;
+        if ids.version > FILE_IDS_VERSION {
+            Err("Book was created by a newer version of Collabook")?
+        }
+        ids.index();
+        Ok(ids)
+    }
+
+    fn write(&self, location: &Path) -> Result<(), MyError> {
+        let contents = toml::to_string(self)?;
+        let mut file = fs::File::create(location.join(".collabook/ids.toml"))?;
+        file.write_all(contents.as_bytes())?;
+        Ok(())
+    }
+
+    fn id_of(&self, rel_path: &Path) -> Result<Option<String>, MyError> {
+        Ok(self.ids.get(&rel_path_key(rel_path)?).cloned())
+    }
+
+    //files without an id get their legacy path hash so their synopsis is still found
+    fn id_for(&mut self, rel_path: &Path) -> Result<String, MyError> {
+        if let Some(id) = self.id_of(rel_path)? {
+            return Ok(id);
+        }
+        let id = legacy_path_id(rel_path)?;
+        self.insert(id.clone(), rel_path_key(rel_path)?);
+        Ok(id)
+    }
+
+    //ids of the files directly inside `rel_path` sorted by name
+    fn children_of(&self, rel_path: &Path) -> Result<Vec<String>, MyError> {
+        let key = rel_path_key(rel_path)?;
+        let mut children: Vec<(&String, &String)> = self
+            .paths
+            .iter()
+            .filter(|(_, path)| match path.rfind('/') {
+                Some(i) => path[..i] == key,
+                None => !path.is_empty() && key.is_empty(),
+            })
+            .collect();
+        children.sort_by(|a, b| a.1.cmp(b.1));
+        Ok(children.into_iter().map(|(id, _)| id.clone()).collect())
+    }
+
+    //ids of `rel_path` and everything inside it
+    fn subtree(&self, rel_path: &Path) -> Result<Vec<String>, MyError> {
+        let key = rel_path_key(rel_path)?;
+        let prefix = format!("{}/", key);
+        Ok(self
+            .paths
+            .iter()
+            .filter(|(_, path)| **path == key || path.starts_with(&prefix))
+            .map(|(id, _)| id.clone())
+            .collect())
+    }
+
+    fn move_subtree(&mut self, from: &Path, to: &Path) -> Result<(), MyError> {
+        let from_key = rel_path_key(from)?;
+        let to_key = rel_path_key(to)?;
+        for id in self.subtree(from)? {
+            if let Some(path) = self.paths.get_mut(&id) {
+                *path = format!("{}{}", to_key, &path[from_key.len()..]);
+                self.changed = true;
+            }
+        }
+        self.index();
+        Ok(())
+    }
+}
+
+const BINDER_ORDER_VERSION: u32 = 1;
+
+/// Manuscript order of the children of every folder, stored in `.collabook/order.toml`.
+/// Maps the id of a folder to the ids of its children.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct BinderOrder {
+    version: u32,
+    children: BTreeMap<String, Vec<String>>,
+}
+
+impl Default for BinderOrder {
+    fn default() -> Self {
+        BinderOrder {
+            version: BINDER_ORDER_VERSION,
+            children: BTreeMap::new(),
+        }
+    }
+}
+
+impl BinderOrder {
+    fn from_files<'a, I: IntoIterator<Item = &'a File>>(files: I) -> Self {
+        let mut order = BinderOrder::default();
+        for file in files {
+            if !file.children.is_empty() {
+                order
+                    .children
+                    .insert(file.id.clone(), file.children.clone());
+            }
+        }
+        order
+    }
+
+    fn read(location: &Path) -> Result<Self, MyError> {
+        let path = location.join(".collabook/order.toml");
+        if !path.exists() {
+            return Ok(BinderOrder::default());
+        }
+        let order: BinderOrder = toml::from_str(&fs::read_to_string(path)?)?;
+        if order.version > BINDER_ORDER_VERSION {
+            Err("Book was created by a newer version of Collabook")?
+        }
+        Ok(order)
+    }
+
+    fn write(&self, location: &Path) -> Result<(), MyError> {
+        let contents = toml::to_string(self)?;
+        let mut file = fs::File::create(location.join(".collabook/order.toml"))?;
+        file.write_all(contents.as_bytes())?;
+        Ok(())
+    }
+
+    //fills `children` of every folder, files missing from the saved order go last sorted by name
+    fn apply(&self, files: &mut HashMap<String, File>) {
+        let mut children: HashMap<String, Vec<(usize, String, String)>> = HashMap::new();
+        for file in files.values() {
+            let position = self
+                .children
+                .get(&file.parent)
+                .and_then(|order| order.iter().position(|id| *id == file.id))
+                .unwrap_or(usize::max_value());
+            children.entry(file.parent.clone()).or_default().push((
+                position,
+                file.name.clone(),
+                file.id.clone(),
+            ));
+        }
+
+        for (parent, mut kids) in children {
+            if let Some(parent) = files.get_mut(&parent) {
+                kids.sort();
+                parent.children = kids.into_iter().map(|(_, _, id)| id).collect();
+            }
+        }
+    }
+
+    fn remove(&mut self, id: &str) {
+        self.children.remove(id);
+        for kids in self.children.values_mut() {
+            kids.retain(|kid| kid != id);
+        }
+    }
+
+    //adds `id` as the last child of `parent`, a folder without a saved order keeps its current
+    //order by name
+    fn push(
+        &mut self,
+        ids: &FileIds,
+        parent: &str,
+        parent_rel_path: &Path,
+        id: &str,
+    ) -> Result<(), MyError> {
+        if !self.children.contains_key(parent) {
+            let mut current = ids.children_of(parent_rel_path)?;
+            current.retain(|kid| kid != id);
+            self.children.insert(parent.to_string(), current);
+        }
+        if let Some(kids) = self.children.get_mut(parent) {
+            kids.push(id.to_string());
+        }
+        Ok(())
+    }
+}
+
 fn is_hidden(entry: &walkdir::DirEntry) -> bool {
     entry
         .file_name()
@@ -143,21 +489,47 @@ fn is_hidden(entry: &walkdir::DirEntry) -> bool {
 
 impl Book {
     fn new<P: AsRef<Path>>(new_book_req: &NewBookRequest<P>) -> Result<Self, MyError> {
-        let mut files = HashMap::new();
+        let template = match new_book_req.template {
+            Some(ref name) => Template::find(name)?,
+            None => Template::for_genre(&new_book_req.genre),
+        };
+
         let root = File::new(&new_book_req.name, "", "0", true, false);
-        let book = File::new("Book", "Book", &root.id, true, false);
-        let chap1 = File::new("Chap1", "Book/Chap1", &book.id, true, false);
-        let sec1 = File::new("Sec1", "Book/Chap1/Sec1", &chap1.id, false, false);
-        let research = File::new("Research", "Research", &root.id, true, true);
-        let chars = File::new("Chars", "Research/Chars", &research.id, false, true);
-        let world = File::new("World", "Research/World", &research.id, false, true);
-        files.insert(root.id.clone(), root);
-        files.insert(book.id.clone(), book);
-        files.insert(chap1.id.clone(), chap1);
-        files.insert(sec1.id.clone(), sec1);
-        files.insert(research.id.clone(), research);
-        files.insert(chars.id.clone(), chars);
-        files.insert(world.id.clone(), world);
+        let mut ids_by_path: HashMap<&str, String> = HashMap::new();
+        ids_by_path.insert("", root.id.clone());
+        let mut scaffold = vec![root];
+        for entry in &template.entries {
+            let (parent_path, name) = match entry.rel_path.rfind('/') {
+                Some(i) => (&entry.rel_path[..i], &entry.rel_path[i + 1..]),
+                None => ("", entry.rel_path.as_str()),
+            };
+            let parent = ids_by_path
+                .get(parent_path)
+                .ok_or("Template entry is listed before its folder")?;
+            let is_research = entry.rel_path.contains("Research");
+            let mut file = File::new(name, &entry.rel_path, parent, entry.is_folder, is_research);
+            file.synopsis = entry.synopsis.clone();
+            if !entry.is_folder {
+                file.content = Some(entry.content.clone());
+            }
+            ids_by_path.insert(&entry.rel_path, file.id.clone());
+            scaffold.push(file);
+        }
+
+        //parents come before their children so children are ordered as they are listed here
+        let mut files: HashMap<String, File> = HashMap::new();
+        for file in scaffold {
+            if let Some(parent) = files.get_mut(&file.parent) {
+                parent.children.push(file.id.clone());
+            }
+            files.insert(file.id.clone(), file);
+        }
+
+        let mut meta = BookMeta::new(&new_book_req.name);
+        meta.genre = Some(new_book_req.genre.clone());
+        if let Ok(author) = Author::read_from(&config_dir()?) {
+            meta.authors.push(author.name);
+        }
 
         //should this be done here or using other request?
         let repo = BookRepo::new(&new_book_req.location)?;
@@ -168,6 +540,7 @@ impl Book {
             files,
             location: new_book_req.location.as_ref().to_path_buf(),
             name: new_book_req.name.to_string(),
+            meta,
             remotes,
             branches,
         })
@@ -183,19 +556,50 @@ impl Book {
                 if let Some(parent) = path.parent() {
                     fs::create_dir_all(&parent)?;
                 }
-                fs::File::create(path)?;
+                let mut f = fs::File::create(path)?;
+                if let Some(ref content) = file.content {
+                    f.write_all(content.as_bytes())?;
+                }
             }
 
             let synopsis_path = &self.location.join(".collabook/synopsis/").join(&file.id);
-            fs::File::create(synopsis_path)?;
+            let mut synopsis = fs::File::create(synopsis_path)?;
+            synopsis.write_all(file.synopsis.as_bytes())?;
         }
+        FileIds::from_files(self.files.values())?.write(&self.location)?;
+        BinderOrder::from_files(self.files.values()).write(&self.location)?;
+        self.meta.write(&self.location)?;
         Ok(())
     }
 
     pub fn open(location: &Path) -> Result<Self, MyError> {
-        //TODO: should ignore target folder
+        let mut book = Book::read(location)?;
 
-        let mut files: HashMap<String, File> = HashMap::new();
+        //TODO: this should be provided as a parameter.. again not sure.
+        let repo = BookRepo::from_location(&location)?;
+        book.remotes = repo._get_remotes()?;
+        book.branches = repo._get_branches()?;
+        Ok(book)
+    }
+
+    /// The book as it was in `revision`, a branch, tag or commit. Its files are exported from
+    /// git into `dir` so images are found like in the working tree, which isn't touched.
+    /// Returns the commit that was read.
+    pub fn open_revision(
+        location: &Path,
+        revision: &str,
+        dir: &Path,
+    ) -> Result<(Self, Oid), MyError> {
+        let book_name = location
+            .file_name()
+            .ok_or("Book location has no directory name")?;
+        let export = dir.join(book_name);
+        let commit = BookRepo::from_location(location)?.export_revision(revision, &export)?;
+        Ok((Book::read(&export)?, commit))
+    }
+
+    fn read(location: &Path) -> Result<Self, MyError> {
+        //TODO: should ignore target folder
 
         //check if is a collabook directory
         //
@@ -208,59 +612,272 @@ impl Book {
             .and_then(|name| name.to_str())
             .ok_or("Filename contains invalid utf-8")?;
 
-        //read files from disk
-        for entry in WalkDir::new(&location)
-            .into_iter()
-            .filter_entry(|e| !is_hidden(e))
-            .filter_map(|e| e.ok())
-        {
-            let f = File::from_location(entry.path(), &location)?;
-            files.insert(f.id.clone(), f);
+        let mut ids = FileIds::read(&location)?;
+        let known: HashSet<String> = ids.paths.keys().cloned().collect();
+        let mut files = Book::read_files(&location, &mut ids)?;
+
+        //files renamed outside of collabook get back their id and so their synopsis and place
+        let added: Vec<PathBuf> = files
+            .values()
+            .filter(|f| !f.is_folder && !known.contains(&f.id))
+            .map(|f| f.rel_path.clone())
+            .collect();
+        let missing: Vec<PathBuf> = ids
+            .paths
+            .iter()
+            .filter(|(id, _)| !files.contains_key(*id))
+            .map(|(_, path)| PathBuf::from(path))
+            .collect();
+        if !added.is_empty() && !missing.is_empty() {
+            if let Ok(repo) = BookRepo::from_location(&location) {
+                let mut reclaimed = false;
+                for (from, to) in repo.renamed_files(&missing, &added)? {
+                    //folders aren't tracked by git, they were renamed when their files were
+                    let mut pairs = vec![(from.as_path(), to.as_path())];
+                    let (mut from, mut to) = (from.parent(), to.parent());
+                    while let (Some(old), Some(new)) = (from, to) {
+                        if old.as_os_str().is_empty() || new.as_os_str().is_empty() {
+                            break;
+                        }
+                        pairs.push((old, new));
+                        from = old.parent();
+                        to = new.parent();
+                    }
+                    for (from, to) in pairs {
+                        if let (Some(old_id), Some(new_id)) = (ids.id_of(from)?, ids.id_of(to)?) {
+                            if !files.contains_key(&old_id) && !known.contains(&new_id) {
+                                ids.remove(&new_id);
+                                ids.insert(old_id, rel_path_key(to)?);
+                                reclaimed = true;
+                            }
+                        }
+                    }
+                }
+                if reclaimed {
+                    files = Book::read_files(&location, &mut ids)?;
+                }
+            }
         }
 
-        //TODO: this should be provided as a parameter.. again not sure.
-        let repo = BookRepo::from_location(&location)?;
-        let remotes = repo._get_remotes()?;
-        let branches = repo._get_branches()?;
+        //forget files which were removed outside of collabook and save newly assigned ids,
+        //this is also where books using path hashes as ids get migrated
+        let removed: Vec<String> = ids
+            .paths
+            .keys()
+            .filter(|id| !files.contains_key(*id))
+            .cloned()
+            .collect();
+        for id in removed {
+            ids.remove(&id);
+        }
+        if ids.changed || ids.version != FILE_IDS_VERSION {
+            ids.version = FILE_IDS_VERSION;
+            ids.write(&location)?;
+        }
+
+        BinderOrder::read(&location)?.apply(&mut files);
+        let meta = BookMeta::read(&location)?;
 
         Ok(Book {
             files,
             location: location.to_path_buf(),
             name: book_name.to_string(),
-            remotes,
-            branches,
+            meta,
+            remotes: Vec::new(),
+            branches: Vec::new(),
         })
     }
 
-    pub fn combine_content<S: AsRef<str>>(&self, ids: &[S]) -> Result<String, MyError> {
-        let mut content = String::new();
+    fn read_files(location: &Path, ids: &mut FileIds) -> Result<HashMap<String, File>, MyError> {
+        let mut files: HashMap<String, File> = HashMap::new();
+        for entry in WalkDir::new(&location)
+            .into_iter()
+            .filter_entry(|e| !is_hidden(e))
+            .filter_map(|e| e.ok())
+        {
+            let f = File::from_location(entry.path(), &location, ids)?;
+            files.insert(f.id.clone(), f);
+        }
+        Ok(files)
+    }
+
+    pub fn sections<S: AsRef<str>>(&self, ids: &[S]) -> Result<Vec<Section>, MyError> {
+        let mut sections = Vec::new();
         for id in ids {
             let file = self.files.get(id.as_ref()).ok_or("File doesn't exist")?;
-            let c = file.content.clone().ok_or("Is a directory")?;
-            content.push_str(&c);
+            let content = file.content.clone().ok_or("Is a directory")?;
+            sections.push(Section {
+                id: file.id.clone(),
+                name: file.name.clone(),
+                content,
+                synopsis: file.synopsis.clone(),
+            });
         }
-        Ok(content)
+        Ok(sections)
+    }
+
+    pub fn chapters<S: AsRef<str>>(&self, ids: &[S]) -> Result<Vec<Chapter>, MyError> {
+        let mut chapters: Vec<Chapter> = Vec::new();
+        for section in self.sections(ids)? {
+            let parent = self.files[&section.id].parent.clone();
+            match chapters.last_mut() {
+                Some(ref mut chapter) if chapter.id == parent => chapter.sections.push(section),
+                _ => {
+                    let title = self
+                        .files
+                        .get(&parent)
+                        .map(|folder| folder.name.clone())
+                        .unwrap_or_default();
+                    chapters.push(Chapter {
+                        id: parent,
+                        title,
+                        sections: vec![section],
+                    });
+                }
+            }
+        }
+        Ok(chapters)
+    }
+
+    /// Id of the root folder, stays the same for the lifetime of the book.
+    pub fn id(&self) -> &str {
+        self.root().map(|root| root.id.as_str()).unwrap_or("")
+    }
+
+    pub fn meta(&self) -> &BookMeta {
+        &self.meta
+    }
+
+    pub fn location(&self) -> &Path {
+        &self.location
+    }
+
+    fn root(&self) -> Option<&File> {
+        self.files.values().find(|f| f.parent == "0")
+    }
+
+    /// The file with `id` followed by everything inside it in manuscript order.
+    fn walk(&self, id: &str) -> Vec<&File> {
+        let mut walked = Vec::new();
+        if let Some(file) = self.files.get(id) {
+            walked.push(file);
+            for child in &file.children {
+                walked.extend(self.walk(child));
+            }
+        }
+        walked
+    }
+
+    /// Ids of the sections inside the folder `id` in manuscript order, research is skipped.
+    pub fn manuscript(&self, id: &str) -> Result<Vec<String>, MyError> {
+        let folder = self.files.get(id).ok_or("File doesn't exist")?;
+        if !folder.is_folder {
+            Err("Not a folder")?
+        }
+        Ok(self
+            .walk(id)
+            .into_iter()
+            .filter(|f| !f.is_folder && !f.is_research)
+            .map(|f| f.id.clone())
+            .collect())
+    }
+
+    /// Sorts `ids` by their position in the binder, unknown ids are kept at the end.
+    pub fn in_binder_order<S: AsRef<str>>(&self, ids: &[S]) -> Vec<String> {
+        let order: HashMap<&str, usize> = match self.root() {
+            Some(root) => self
+                .walk(&root.id)
+                .iter()
+                .enumerate()
+                .map(|(i, f)| (f.id.as_str(), i))
+                .collect(),
+            None => HashMap::new(),
+        };
+        let mut sorted: Vec<String> = ids.iter().map(|id| id.as_ref().to_string()).collect();
+        sorted.sort_by_key(|id| {
+            order
+                .get(id.as_str())
+                .cloned()
+                .unwrap_or(usize::max_value())
+        });
+        sorted
     }
 }
 
+/// What to compile and how, shared by compile requests and compile presets.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CompileOptions {
+    #[serde(default)]
+    pub ids: Vec<String>,
+    #[serde(default)]
+    pub in_binder_order: bool,
+    /// Compile every section inside this folder in binder order instead of `ids`
+    #[serde(default)]
+    pub root: Option<String>,
+    /// Start every chapter with the name of its folder, by default only when compiling a folder
+    /// with `root`
+    #[serde(default)]
+    pub chapter_headings: Option<bool>,
+    #[serde(default)]
+    pub markup: Markup,
+    #[serde(default)]
+    pub format: OutputFormat,
+    /// Pdf renderer to use, the built-in typesetter when not set
+    #[serde(default)]
+    pub renderer: Option<String>,
+    /// Styling of pdf, html and epub output, see `Theme::find`
+    #[serde(default)]
+    pub theme: Option<String>,
+    /// Attach section synopses as comments, only used by docx
+    #[serde(default)]
+    pub synopsis_comments: bool,
+    /// Turn straight quotes, `--`, `---` and `...` into curly quotes, dashes and ellipses.
+    /// Code is left as it was typed. Off unless the compile asks for it.
+    #[serde(default)]
+    pub typography: bool,
+    /// Quote style and spacing of the typography, from the language of the book when not set
+    #[serde(default)]
+    pub typography_locale: Option<Locale>,
+    /// Title page, copyright, dedication, contents and about the author pages to generate
+    #[serde(default)]
+    pub matter: Matter,
+    #[serde(default)]
+    pub latex: LatexOptions,
+    /// Font, header title and contact details of manuscript output
+    #[serde(default)]
+    pub manuscript: ManuscriptOptions,
+    /// Page setup of pdf output
+    #[serde(default)]
+    pub layout: Layout,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
-pub struct CompileBookRequest<P: AsRef<Path> = PathBuf, S: AsRef<str> = String> {
+pub struct CompileBookRequest<P: AsRef<Path> = PathBuf> {
     pub location: P,
-    pub ids: Vec<S>,
+    /// Directory in `target/exports/` of the book the output is copied to, the build in
+    /// `target/builds/` keeps its own copy either way.
+    #[serde(default)]
+    pub output_dir: Option<PathBuf>,
+    /// Name of the output file or directory, the extension of the format is added
+    #[serde(default)]
+    pub filename: Option<String>,
+    /// Branch, tag or commit to compile instead of the working tree
+    #[serde(default)]
+    pub revision: Option<String>,
+    #[serde(flatten)]
+    pub options: CompileOptions,
 }
 
+/// Queues the compile and returns the job right away, its progress can be polled by id.
 pub fn compile_book(
     (state, info): (State<AppState>, Json<CompileBookRequest>),
-) -> FutureResponse<HttpResponse> {
-    state
-        .compiler
-        .send(info.into_inner())
-        .from_err()
-        .and_then(|res| match res {
-            Ok(()) => Ok(HttpResponse::Ok().into()),
-            Err(e) => Ok(HttpResponse::InternalServerError().body(e.to_string())),
-        })
-        .responder()
+) -> Result<impl Responder, MyError> {
+    let job = state.jobs.queue(&info.location, info.options.format);
+    state.compiler.do_send(CompileJob {
+        id: job.id.clone(),
+        request: info.into_inner(),
+    });
+    Ok(HttpResponse::Accepted().json(job))
 }
 
 pub fn new_book<P: AsRef<Path>>(info: Json<NewBookRequest<P>>) -> Result<impl Responder, MyError> {
@@ -282,6 +899,22 @@ pub fn open_book(info: Json<BookLocation<PathBuf>>) -> Result<impl Responder, My
     Ok(ser_book)
 }
 
+#[derive(Deserialize, Debug)]
+pub struct SaveBookMetaRequest {
+    location: PathBuf,
+    meta: BookMeta,
+}
+
+pub fn save_book_meta(info: Json<SaveBookMetaRequest>) -> Result<impl Responder, MyError> {
+    if !info.location.join(".collabook").exists() {
+        Err("Not a Collabook directory")?
+    }
+    let mut meta = info.meta.clone();
+    meta.version = BOOK_META_VERSION;
+    meta.write(&info.location)?;
+    Ok(HttpResponse::Ok().json(meta))
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct NewFileRequest {
     parent_id: String,
@@ -293,8 +926,7 @@ pub struct NewFileRequest {
 
 pub fn new_file(info: Json<NewFileRequest>) -> Result<impl Responder, MyError> {
     let rel_path = &info.parent_rel_path.join(&info.name);
-    let rel_path_str = rel_path.to_str().ok_or("Filename contains invalid utf-8")?;
-    let id = Sha1::from(rel_path_str).digest().to_string();
+    let id = new_id();
 
     let is_research = rel_path.to_string_lossy().contains("Research");
 
@@ -307,6 +939,15 @@ pub fn new_file(info: Json<NewFileRequest>) -> Result<impl Responder, MyError> {
         content = Some("".to_string());
     }
     fs::File::create(&info.location.join(".collabook/synopsis").join(&id))?;
+
+    let mut ids = FileIds::read(&info.location)?;
+    ids.insert(id.clone(), rel_path_key(rel_path)?);
+    ids.write(&info.location)?;
+
+    let mut order = BinderOrder::read(&info.location)?;
+    order.push(&ids, &info.parent_id, &info.parent_rel_path, &id)?;
+    order.write(&info.location)?;
+
     let f = File {
         id,
         name: info.name.clone(),
@@ -317,6 +958,7 @@ pub fn new_file(info: Json<NewFileRequest>) -> Result<impl Responder, MyError> {
         is_research,
         content,
         synopsis: "".to_string(),
+        children: Vec::new(),
     };
     let ser_f = serde_json::to_string(&f)?;
     Ok(HttpResponse::Ok().body(ser_f))
@@ -357,15 +999,181 @@ pub struct DeleteFileRequest {
 
 pub fn delete_file(info: Json<DeleteFileRequest>) -> Result<impl Responder, MyError> {
     let path = &info.location.join(&info.rel_path);
+
+    //remove the synopsis of everything inside a deleted folder too, files of a book which
+    //hasn't been migrated yet are only known by their path so they get their id first
+    let mut ids = FileIds::read(&info.location)?;
+    for entry in WalkDir::new(&path).into_iter().filter_map(|e| e.ok()) {
+        ids.id_for(&entry.path().strip_prefix(&info.location)?)?;
+    }
     if path.is_dir() {
         fs::remove_dir_all(&path)?;
     } else {
         fs::remove_file(&path)?;
     }
-    fs::remove_file(&info.location.join(".collabook/synopsis").join(&info.id))?;
+
+    let mut order = BinderOrder::read(&info.location)?;
+    let mut removed = ids.subtree(&info.rel_path)?;
+    if !removed.contains(&info.id) {
+        removed.push(info.id.clone());
+    }
+    for id in &removed {
+        let synopsis_path = info.location.join(".collabook/synopsis").join(id);
+        if synopsis_path.exists() {
+            fs::remove_file(&synopsis_path)?;
+        }
+        ids.remove(id);
+        order.remove(id);
+    }
+    ids.write(&info.location)?;
+    order.write(&info.location)?;
     Ok("Deleted file".to_string())
 }
 
+#[derive(Deserialize, Debug)]
+pub struct MoveFileRequest {
+    location: PathBuf,
+    rel_path: PathBuf,
+    new_rel_path: PathBuf,
+}
+
+// Moves a file or a whole folder to `to`, both relative to the book location. Ids don't depend
+// on the path so only the paths in `.collabook/ids.toml` change and every synopsis stays where it
+// is. The updated files are returned with the moved file first.
+fn move_file_on_disk(location: &Path, from: &Path, to: &Path) -> Result<Vec<File>, MyError> {
+    let old_path = location.join(from);
+    let new_path = location.join(to);
+
+    let inside = |path: &Path| {
+        path.components().all(|component| match component {
+            Component::Normal(_) => true,
+            _ => false,
+        })
+    };
+    if !inside(from) || !inside(to) || to.as_os_str().is_empty() {
+        Err("Files can only be moved inside the book")?
+    }
+    if from.as_os_str().is_empty() || !old_path.exists() {
+        Err("File doesn't exist")?
+    }
+    if new_path.exists() {
+        Err("A file with the same name already exists")?
+    }
+    if to.starts_with(from) {
+        Err("Cannot move a folder inside itself")?
+    }
+
+    //paths of the subtree relative to the moved file, the moved file itself is an empty path
+    let mut suffixes: Vec<PathBuf> = Vec::new();
+    for entry in WalkDir::new(&old_path).into_iter().filter_map(|e| e.ok()) {
+        suffixes.push(entry.path().strip_prefix(&old_path)?.to_path_buf());
+    }
+    let join = |base: &Path, suffix: &Path| {
+        if suffix.as_os_str().is_empty() {
+            base.to_path_buf()
+        } else {
+            base.join(suffix)
+        }
+    };
+
+    //make sure files of a book which hasn't been migrated yet keep their current id
+    let mut ids = FileIds::read(location)?;
+    for suffix in &suffixes {
+        ids.id_for(&join(from, suffix))?;
+    }
+    let old_ids = ids.clone();
+    let old_order = BinderOrder::read(location)?;
+
+    ids.move_subtree(from, to)?;
+    //a moved file goes to the end of its new folder, a renamed one keeps its place
+    let mut order = old_order.clone();
+    let id = ids.id_for(to)?;
+    let new_parent = to.parent().unwrap_or_else(|| Path::new(""));
+    let reorder = from.parent() != to.parent();
+    if reorder {
+        for kids in order.children.values_mut() {
+            kids.retain(|kid| *kid != id);
+        }
+        let parent_id = ids.id_for(new_parent)?;
+        order.push(&ids, &parent_id, new_parent, &id)?;
+    }
+
+    //ids and order are written before the file is moved and put back when the move fails, so
+    //they never point at a path the file isn't at
+    let moved = ids
+        .write(location)
+        .and_then(|_| {
+            if reorder {
+                order.write(location)
+            } else {
+                Ok(())
+            }
+        })
+        .and_then(|_| match new_path.parent() {
+            Some(parent) => Ok(fs::create_dir_all(&parent)?),
+            None => Ok(()),
+        })
+        .and_then(|_| Ok(fs::rename(&old_path, &new_path)?));
+    if let Err(e) = moved {
+        let _ = old_ids.write(location);
+        if reorder {
+            let _ = old_order.write(location);
+        }
+        return Err(e);
+    }
+
+    //stage the move so that git sees a rename instead of a deleted and an untracked file
+    let repo = BookRepo::from_location(location)?;
+    repo._stage_move(&[from], &[to])?;
+
+    let mut files = Vec::new();
+    for suffix in &suffixes {
+        files.push(File::from_location::<&Path>(
+            &location.join(join(to, suffix)),
+            location,
+            &mut ids,
+        )?);
+    }
+    Ok(files)
+}
+
+pub fn move_file(info: Json<MoveFileRequest>) -> Result<impl Responder, MyError> {
+    let files = move_file_on_disk(&info.location, &info.rel_path, &info.new_rel_path)?;
+    Ok(HttpResponse::Ok().json(files))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ReorderRequest {
+    location: PathBuf,
+    parent_id: String,
+    children: Vec<String>,
+}
+
+fn reorder_children(location: &Path, parent_id: &str, children: &[String]) -> Result<(), MyError> {
+    let book = Book::open(location)?;
+    let parent = book.files.get(parent_id).ok_or("File doesn't exist")?;
+
+    let mut current = parent.children.clone();
+    let mut requested = children.to_vec();
+    current.sort();
+    requested.sort();
+    if current != requested {
+        Err("Children don't match the contents of the folder")?
+    }
+
+    let mut order = BinderOrder::read(location)?;
+    order
+        .children
+        .insert(parent_id.to_string(), children.to_vec());
+    order.write(location)?;
+    Ok(())
+}
+
+pub fn reorder(info: Json<ReorderRequest>) -> Result<impl Responder, MyError> {
+    reorder_children(&info.location, &info.parent_id, &info.children)?;
+    Ok(HttpResponse::Ok())
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Save {
     content: String,
@@ -380,11 +1188,26 @@ pub enum AuthType {
     SSHPath { path: String },
 }
 
-const APP_INFO: AppInfo = AppInfo {
+pub const APP_INFO: AppInfo = AppInfo {
     name: "Collabook",
     author: "Akhil",
 };
 
+/// Directory of the author config and user templates.
+#[cfg(not(test))]
+pub fn config_dir() -> Result<PathBuf, MyError> {
+    Ok(app_dirs::app_root(AppDataType::UserConfig, &APP_INFO)?)
+}
+
+//every test thread gets an empty config dir of its own instead of the one of the user
+#[cfg(test)]
+pub fn config_dir() -> Result<PathBuf, MyError> {
+    thread_local! {
+        static CONFIG_DIR: tempdir::TempDir = tempdir::TempDir::new("config").unwrap();
+    }
+    Ok(CONFIG_DIR.with(|dir| dir.path().to_path_buf()))
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Author {
     pub name: String,
@@ -401,7 +1224,11 @@ impl crate::github::AccessToken for Author {
 
 impl Author {
     pub fn read_from_disk() -> Result<Self, MyError> {
-        let path = app_dirs::app_root(AppDataType::UserConfig, &APP_INFO)?;
+        Author::read_from(&app_dirs::app_root(AppDataType::UserConfig, &APP_INFO)?)
+    }
+
+    /// Reads `Config.toml` of the config dir `path`.
+    pub fn read_from(path: &Path) -> Result<Self, MyError> {
         let mut file = fs::File::open(path.join("Config.toml"))?;
         let mut contents = String::new();
         file.read_to_string(&mut contents)?;
@@ -478,8 +1305,10 @@ mod tests {
         )
         .unwrap();
 
+        let mut ids = FileIds::default();
         let sec1 =
-            File::from_location::<&Path>(&temp_dir.path().join("Sec1"), temp_dir.path()).unwrap();
+            File::from_location::<&Path>(&temp_dir.path().join("Sec1"), temp_dir.path(), &mut ids)
+                .unwrap();
         assert_eq!(sec1.is_folder, false);
         assert_eq!(sec1.content, Some("".to_string()));
         assert_eq!(sec1.name, "Sec1".to_string());
@@ -496,7 +1325,8 @@ mod tests {
         //add some content to Research/Chars file
         f.write_all(b"some content").unwrap();
 
-        let chars = File::from_location::<&Path>(&path.join("Research/Chars"), path).unwrap();
+        let chars =
+            File::from_location::<&Path>(&path.join("Research/Chars"), path, &mut ids).unwrap();
         assert_eq!(chars.is_folder, false);
         assert_eq!(chars.is_research, true);
         assert_eq!(chars.content, Some("some content".to_string()));
@@ -505,13 +1335,14 @@ mod tests {
     #[test]
     fn test_file_constructor() {
         let root = File::new("testbook", "", "0", true, false);
-        assert_eq!(&root.id, "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_ne!(&root.id, "da39a3ee5e6b4b0d3255bfef95601890afd80709");
         assert_eq!(root.content.is_none(), true);
         assert_eq!(&root.parent, "0");
         assert_eq!(&root.rel_path, Path::new(""));
 
         let sec1 = File::new("sec1", "Book/Chap1/Sec1", &root.id, false, false);
-        assert_eq!(&sec1.id, "0ad0fd5d1787ebf9465fb46c743d35eb6b9ab783");
+        let sec1_again = File::new("sec1", "Book/Chap1/Sec1", &root.id, false, false);
+        assert_ne!(&sec1.id, &sec1_again.id);
         assert_eq!(&sec1.content.unwrap(), "");
     }
 
@@ -523,10 +1354,84 @@ mod tests {
             name: "test_book".to_string(),
             location: &path,
             genre: Genre::Fantasy,
+            template: None,
         });
         new_book(req).unwrap();
         assert_eq!(path.join("Book/Chap1/Sec1").exists(), true);
         assert_eq!(path.join("Research/Chars").exists(), true);
+        assert_eq!(path.join("Research/Magic System").exists(), true);
+
+        let book = Book::open(&path).unwrap();
+        let glossary = &book.files[&id_of(&book, "Research/Glossary")];
+        assert_eq!(glossary.is_research, true);
+        assert_eq!(
+            glossary.synopsis,
+            "Invented words, names and their meanings"
+        );
+    }
+
+    #[test]
+    fn open_revision_reads_the_commit_and_leaves_the_checkout() {
+        let temp_dir = TempDir::new("test_dir").unwrap();
+        let path = temp_dir.path().join("test_book");
+        new_book(Json(NewBookRequest {
+            name: "test_book".to_string(),
+            location: &path,
+            genre: Genre::Fantasy,
+            template: None,
+        }))
+        .unwrap();
+        fs::write(path.join("Book/Chap1/Sec1"), "draft 2").unwrap();
+
+        let repo = git2::Repository::open(&path).unwrap();
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"].iter(), git2::IndexAddOption::empty(), None)
+            .unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("name", "email").unwrap();
+        let oid = repo
+            .commit(Some("HEAD"), &signature, &signature, "draft", &tree, &[])
+            .unwrap();
+        repo.tag_lightweight("draft-2", &repo.find_object(oid, None).unwrap(), false)
+            .unwrap();
+        fs::write(path.join("Book/Chap1/Sec1"), "draft 3").unwrap();
+        fs::remove_file(path.join("Research/Chars")).unwrap();
+
+        let export = TempDir::new("test_export").unwrap();
+        let (book, commit) = Book::open_revision(&path, "draft-2", export.path()).unwrap();
+        assert_eq!(commit, oid);
+        assert_eq!(book.location(), export.path().join("test_book"));
+        let sec1 = &book.files[&id_of(&book, "Book/Chap1/Sec1")];
+        assert_eq!(sec1.content, Some("draft 2".to_string()));
+        assert_eq!(
+            sec1.synopsis,
+            Book::open(&path).unwrap().files[&sec1.id].synopsis
+        );
+        assert!(book.files.values().any(|f| f.name == "Chars"));
+
+        assert_eq!(
+            fs::read_to_string(path.join("Book/Chap1/Sec1")).unwrap(),
+            "draft 3"
+        );
+        assert!(!path.join("Research/Chars").exists());
+        assert!(Book::open_revision(&path, "draft-9", export.path()).is_err());
+    }
+
+    #[test]
+    fn new_book_uses_requested_template() {
+        let temp_dir = TempDir::new("test_dir").unwrap();
+        let path = temp_dir.path().join("test_book");
+        let req = Json(NewBookRequest {
+            name: "test_book".to_string(),
+            location: &path,
+            genre: Genre::Fantasy,
+            template: Some("academic".to_string()),
+        });
+        new_book(req).unwrap();
+        assert_eq!(path.join("Book/Abstract").exists(), true);
+        assert_eq!(path.join("Book/Chapters/Chap1/Sec1").exists(), true);
+        assert_eq!(path.join("Research/Magic System").exists(), false);
     }
 
     #[test]
@@ -579,6 +1484,322 @@ mod tests {
         );
     }
 
+    #[test]
+    fn move_folder_keeps_ids_and_synopsis() {
+        let temp_dir = TempDir::new("test_dir").unwrap();
+        let path = temp_dir.path().join("test_book");
+        let req = Json(NewBookRequest {
+            name: "test_book".to_string(),
+            location: &path,
+            genre: Genre::Fantasy,
+            template: None,
+        });
+        new_book(req).unwrap();
+
+        let ids = FileIds::read(&path).unwrap();
+        let chap1_id = ids.id_of(Path::new("Book/Chap1")).unwrap().unwrap();
+        let sec1_id = ids.id_of(Path::new("Book/Chap1/Sec1")).unwrap().unwrap();
+        fs::write(
+            path.join(".collabook/synopsis").join(&sec1_id),
+            "sec1 synopsis",
+        )
+        .unwrap();
+        fs::write(path.join("Book/Chap1/Sec1"), "sec1 content").unwrap();
+
+        let files =
+            move_file_on_disk(&path, Path::new("Book/Chap1"), Path::new("Book/Part1")).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].name, "Part1");
+        assert_eq!(files[0].id, chap1_id);
+
+        let sec1 = &files[1];
+        assert_eq!(sec1.id, sec1_id);
+        assert_eq!(sec1.parent, chap1_id);
+        assert_eq!(sec1.rel_path, Path::new("Book/Part1/Sec1"));
+        assert_eq!(sec1.synopsis, "sec1 synopsis");
+        assert_eq!(sec1.content, Some("sec1 content".to_string()));
+
+        let book = Book::open(&path).unwrap();
+        assert_eq!(book.files[&sec1_id].rel_path, Path::new("Book/Part1/Sec1"));
+
+        let repo = BookRepo::from_location(&path).unwrap();
+        let index = repo.index().unwrap();
+        assert!(index.get_path(Path::new("Book/Part1/Sec1"), 0).is_some());
+        assert!(index.get_path(Path::new("Book/Chap1/Sec1"), 0).is_none());
+    }
+
+    #[test]
+    fn move_folder_inside_itself_gives_error() {
+        let temp_dir = TempDir::new("test_dir").unwrap();
+        let path = temp_dir.path().join("test_book");
+        let req = Json(NewBookRequest {
+            name: "test_book".to_string(),
+            location: &path,
+            genre: Genre::Fantasy,
+            template: None,
+        });
+        new_book(req).unwrap();
+
+        let res = move_file_on_disk(&path, Path::new("Book"), Path::new("Book/Chap1/Book"));
+        assert!(res.is_err());
+        assert!(path.join("Book/Chap1/Sec1").exists());
+    }
+
+    #[test]
+    fn move_outside_the_book_gives_error() {
+        let temp_dir = TempDir::new("test_dir").unwrap();
+        let path = temp_dir.path().join("test_book");
+        let req = Json(NewBookRequest {
+            name: "test_book".to_string(),
+            location: &path,
+            genre: Genre::Fantasy,
+            template: None,
+        });
+        new_book(req).unwrap();
+        let ids = fs::read_to_string(path.join(".collabook/ids.toml")).unwrap();
+
+        let outside = temp_dir.path().join("Chap1");
+        for to in &[
+            Path::new("../Chap1"),
+            &outside,
+            Path::new("Book/../../Chap2"),
+        ] {
+            assert!(move_file_on_disk(&path, Path::new("Book/Chap1"), to).is_err());
+        }
+        assert!(
+            move_file_on_disk(&path, Path::new("../test_book/Book"), Path::new("Other")).is_err()
+        );
+        assert!(path.join("Book/Chap1/Sec1").exists());
+        assert!(!outside.exists());
+        assert_eq!(
+            fs::read_to_string(path.join(".collabook/ids.toml")).unwrap(),
+            ids
+        );
+    }
+
+    #[test]
+    fn open_book_migrates_path_hash_ids() {
+        let temp_dir = TempDir::new("test_dir").unwrap();
+        let path = temp_dir.path().join("test_book");
+        let sec1_sha1 = "169a91e9a0699ef3d8cee8f29a76856498ef0c0e";
+
+        fs::create_dir_all(&path.join("Book")).unwrap();
+        fs::create_dir_all(&path.join(".collabook/synopsis")).unwrap();
+        fs::File::create(&path.join("Book/Sec1")).unwrap();
+        for id in &[
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+            "f69f233005f15802770fd26fbf7ead52ec13d9e6",
+            sec1_sha1,
+        ] {
+            fs::File::create(&path.join(".collabook/synopsis").join(id)).unwrap();
+        }
+        git2::Repository::init(&path).unwrap();
+        assert!(!path.join(".collabook/ids.toml").exists());
+
+        Book::open(&path).unwrap();
+        let ids = FileIds::read(&path).unwrap();
+        assert_eq!(ids.paths.len(), 3);
+        assert_eq!(ids.paths[sec1_sha1], "Book/Sec1");
+
+        //opening again assigns nothing new and leaves the file as it is
+        let ids_path = path.join(".collabook/ids.toml");
+        let mut contents = fs::read_to_string(&ids_path).unwrap();
+        contents.push_str("# unchanged\n");
+        fs::write(&ids_path, &contents).unwrap();
+        Book::open(&path).unwrap();
+        assert_eq!(fs::read_to_string(&ids_path).unwrap(), contents);
+
+        //the id survives a rename once it has been stored
+        fs::rename(path.join("Book/Sec1"), path.join("Book/Prologue")).unwrap();
+        let ids = FileIds::read(&path).unwrap();
+        let mut moved = ids.clone();
+        moved
+            .move_subtree(Path::new("Book/Sec1"), Path::new("Book/Prologue"))
+            .unwrap();
+        moved.write(&path).unwrap();
+        let book = Book::open(&path).unwrap();
+        assert_eq!(book.files[sec1_sha1].name, "Prologue");
+    }
+
+    #[test]
+    fn legacy_books_without_synopses_open_and_delete_nested_synopses() {
+        let temp_dir = TempDir::new("test_dir").unwrap();
+        let path = temp_dir.path().join("test_book");
+        let chap1_sha1 = legacy_path_id(Path::new("Book/Chap1")).unwrap();
+        let sec1_sha1 = legacy_path_id(Path::new("Book/Chap1/Sec1")).unwrap();
+
+        fs::create_dir_all(&path.join("Book/Chap1")).unwrap();
+        fs::create_dir_all(&path.join(".collabook/synopsis")).unwrap();
+        fs::File::create(&path.join("Book/Chap1/Sec1")).unwrap();
+        fs::File::create(&path.join("Book/Sec2")).unwrap();
+        fs::File::create(&path.join(".collabook/synopsis").join(&sec1_sha1)).unwrap();
+        git2::Repository::init(&path).unwrap();
+
+        //deleting a folder before the book was migrated removes the synopses inside it
+        delete_file(Json(DeleteFileRequest {
+            location: path.clone(),
+            rel_path: PathBuf::from("Book/Chap1"),
+            id: chap1_sha1,
+        }))
+        .unwrap();
+        assert!(!path.join(".collabook/synopsis").join(&sec1_sha1).exists());
+
+        //the rest never had a synopsis and reads as empty
+        let book = Book::open(&path).unwrap();
+        assert_eq!(book.files.len(), 3);
+        assert!(book.files.values().all(|f| f.synopsis.is_empty()));
+    }
+
+    #[test]
+    fn files_renamed_outside_collabook_keep_their_id() {
+        let temp_dir = TempDir::new("test_dir").unwrap();
+        let path = temp_dir.path().join("test_book");
+        let req = Json(NewBookRequest {
+            name: "test_book".to_string(),
+            location: &path,
+            genre: Genre::Fantasy,
+            template: None,
+        });
+        new_book(req).unwrap();
+
+        let book = Book::open(&path).unwrap();
+        let chap1 = id_of(&book, "Book/Chap1");
+        let sec1 = id_of(&book, "Book/Chap1/Sec1");
+        fs::write(
+            path.join("Book/Chap1/Sec1"),
+            "It was a dark and stormy night.",
+        )
+        .unwrap();
+        fs::write(path.join(".collabook/synopsis").join(&sec1), "The storm").unwrap();
+        let repo = BookRepo::from_location(&path).unwrap();
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.write().unwrap();
+
+        fs::rename(path.join("Book/Chap1"), path.join("Book/Part1")).unwrap();
+        fs::rename(
+            path.join("Book/Part1/Sec1"),
+            path.join("Book/Part1/Opening"),
+        )
+        .unwrap();
+
+        let book = Book::open(&path).unwrap();
+        assert_eq!(book.files[&chap1].rel_path, Path::new("Book/Part1"));
+        assert_eq!(book.files[&sec1].rel_path, Path::new("Book/Part1/Opening"));
+        assert_eq!(book.files[&sec1].synopsis, "The storm");
+        assert_eq!(book.files[&sec1].parent, chap1);
+    }
+
+    #[test]
+    fn open_book_returns_children_in_binder_order() {
+        let temp_dir = TempDir::new("test_dir").unwrap();
+        let path = temp_dir.path().join("test_book");
+        let req = Json(NewBookRequest {
+            name: "test_book".to_string(),
+            location: &path,
+            genre: Genre::Fantasy,
+            template: None,
+        });
+        new_book(req).unwrap();
+
+        let book = Book::open(&path).unwrap();
+        let chap1 = id_of(&book, "Book/Chap1");
+        let sec1 = id_of(&book, "Book/Chap1/Sec1");
+
+        //new files go last even if their name sorts first
+        let req = Json(NewFileRequest {
+            parent_id: chap1.clone(),
+            name: "Prologue".to_string(),
+            is_folder: false,
+            location: path.clone(),
+            parent_rel_path: PathBuf::from("Book/Chap1"),
+        });
+        new_file(req).unwrap();
+        let book = Book::open(&path).unwrap();
+        let prologue = id_of(&book, "Book/Chap1/Prologue");
+        assert_eq!(
+            book.files[&chap1].children,
+            vec![sec1.clone(), prologue.clone()]
+        );
+
+        reorder_children(&path, &chap1, &[prologue.clone(), sec1.clone()]).unwrap();
+        let book = Book::open(&path).unwrap();
+        assert_eq!(
+            book.files[&chap1].children,
+            vec![prologue.clone(), sec1.clone()]
+        );
+        assert_eq!(
+            book.in_binder_order(&[sec1.clone(), prologue.clone()]),
+            vec![prologue.clone(), sec1.clone()]
+        );
+
+        //the list has to contain exactly the children of the folder
+        assert!(reorder_children(&path, &chap1, &[sec1.clone()]).is_err());
+    }
+
+    #[test]
+    fn book_meta_is_written_and_read() {
+        let temp_dir = TempDir::new("test_dir").unwrap();
+        let path = temp_dir.path().join("test_book");
+        let req = Json(NewBookRequest {
+            name: "test_book".to_string(),
+            location: &path,
+            genre: Genre::Academic,
+            template: None,
+        });
+        new_book(req).unwrap();
+        assert!(path.join(".collabook/book.toml").exists());
+
+        let book = Book::open(&path).unwrap();
+        assert_eq!(book.meta.title, "test_book");
+        assert_eq!(book.meta.genre, Some(Genre::Academic));
+
+        let mut meta = book.meta.clone();
+        meta.title = "The Test Book".to_string();
+        meta.subtitle = Some("A subtitle".to_string());
+        meta.isbn = Some("978-3-16-148410-0".to_string());
+        save_book_meta(Json(SaveBookMetaRequest {
+            location: path.clone(),
+            meta,
+        }))
+        .unwrap();
+
+        let book = Book::open(&path).unwrap();
+        assert_eq!(book.meta.title, "The Test Book");
+        assert_eq!(book.meta.subtitle, Some("A subtitle".to_string()));
+        assert_eq!(book.meta.isbn, Some("978-3-16-148410-0".to_string()));
+    }
+
+    #[test]
+    fn new_book_takes_the_author_from_the_config_dir() {
+        let temp_dir = TempDir::new("test_dir").unwrap();
+        let new_book_at = |name: &str| {
+            let req = NewBookRequest {
+                name: name.to_string(),
+                location: temp_dir.path().join(name),
+                genre: Genre::Fiction,
+                template: None,
+            };
+            Book::new(&req).unwrap()
+        };
+        assert!(new_book_at("first").meta.authors.is_empty());
+
+        let author = Author {
+            name: "akhil".to_string(),
+            email: "email".to_string(),
+            token: "token".to_string(),
+            auth: AuthType::SSHAgent,
+        };
+        fs::write(
+            config_dir().unwrap().join("Config.toml"),
+            toml::to_string(&author).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(new_book_at("second").meta.authors, vec!["akhil"]);
+    }
+
     #[test]
     #[should_panic(expected = "Not a Collabook directory")]
     fn opening_not_a_book_gives_error() {
@@ -615,6 +1836,7 @@ mod tests {
             name: "test_book".to_string(),
             location: &path,
             genre: Genre::Fantasy,
+            template: None,
         });
         let book = Book::new(&req).unwrap();
         book.compile("<p>some content</p>").unwrap();
@@ -622,6 +1844,14 @@ mod tests {
     }
     */
 
+    fn id_of(book: &Book, rel_path: &str) -> String {
+        book.files
+            .values()
+            .find(|f| f.rel_path == Path::new(rel_path))
+            .map(|f| f.id.clone())
+            .unwrap()
+    }
+
     fn setup_book() -> (Book, TempDir) {
         let temp_dir = TempDir::new("test_dir").unwrap();
         let path = temp_dir.path().join("test_book");
@@ -650,6 +1880,7 @@ mod tests {
             files,
             location: path,
             name: "test_book".to_string(),
+            meta: BookMeta::new("test_book"),
             remotes,
             branches,
         };
@@ -657,28 +1888,77 @@ mod tests {
     }
 
     #[test]
-    fn combine_content_works() {
+    fn chapters_group_sections_by_folder() {
         let (mut book, _) = setup_book();
 
         let ids = [
-            "0ad0fd5d1787ebf9465fb46c743d35eb6b9ab783",
-            "ad547798d6f4c6e1224226f5bd5253b93fde470f",
-            "722935af1ff2d97062f48532f2ef95827da39b93",
+            id_of(&book, "Book/Chap1/Sec1"),
+            id_of(&book, "Book/Chap1/Sec2"),
+            id_of(&book, "Book/Chap2/Sec1"),
         ];
-        let mut c1_s1 = book.files.get_mut(ids[0]).unwrap();
-        c1_s1.content = Some("hello from sec1\n".to_string());
+        book.files.get_mut(&ids[0]).unwrap().content = Some("hello from sec1\n".to_string());
+        book.files.get_mut(&ids[1]).unwrap().content = Some("hello from sec2\n".to_string());
+        book.files.get_mut(&ids[2]).unwrap().content = Some("hello from chap2 sec1".to_string());
+
+        let chapters = book.chapters(&ids).unwrap();
+        let titles: Vec<&str> = chapters.iter().map(|c| c.title.as_str()).collect();
+        assert_eq!(titles, vec!["Chap1", "Chap2"]);
+        let content: String = chapters
+            .iter()
+            .flat_map(|c| &c.sections)
+            .map(|s| s.content.as_str())
+            .collect();
+        assert_eq!(
+            content,
+            "hello from sec1\nhello from sec2\nhello from chap2 sec1".to_string()
+        );
+    }
 
-        let mut c1_s2 = book.files.get_mut(ids[1]).unwrap();
-        c1_s2.content = Some("hello from sec2\n".to_string());
+    #[test]
+    fn manuscript_follows_binder_and_skips_research() {
+        let temp_dir = TempDir::new("test_dir").unwrap();
+        let path = temp_dir.path().join("test_book");
+        let req = Json(NewBookRequest {
+            name: "test_book".to_string(),
+            location: &path,
+            genre: Genre::Fantasy,
+            template: None,
+        });
+        new_book(req).unwrap();
 
-        let mut c2_s1 = book.files.get_mut(ids[2]).unwrap();
-        c2_s1.content = Some("hello from chap2 sec1".to_string());
+        let req = Json(NewFileRequest {
+            parent_id: id_of(&Book::open(&path).unwrap(), "Book"),
+            name: "Chap2".to_string(),
+            is_folder: true,
+            location: path.clone(),
+            parent_rel_path: PathBuf::from("Book"),
+        });
+        new_file(req).unwrap();
+        let req = Json(NewFileRequest {
+            parent_id: id_of(&Book::open(&path).unwrap(), "Book/Chap2"),
+            name: "Sec1".to_string(),
+            is_folder: false,
+            location: path.clone(),
+            parent_rel_path: PathBuf::from("Book/Chap2"),
+        });
+        new_file(req).unwrap();
 
-        let content = book.combine_content(&ids).unwrap();
+        let book = Book::open(&path).unwrap();
+        let c1_s1 = id_of(&book, "Book/Chap1/Sec1");
+        let c2_s1 = id_of(&book, "Book/Chap2/Sec1");
         assert_eq!(
-            content,
-            "hello from sec1\nhello from sec2\nhello from chap2 sec1".to_string()
-        )
+            book.manuscript(&id_of(&book, "Book")).unwrap(),
+            vec![c1_s1.clone(), c2_s1.clone()]
+        );
+        assert_eq!(
+            book.manuscript(&id_of(&book, "Book/Chap2")).unwrap(),
+            vec![c2_s1.clone()]
+        );
+
+        let root = book.id().to_string();
+        let whole = book.manuscript(&root).unwrap();
+        assert_eq!(whole, vec![c1_s1.clone(), c2_s1.clone()]);
+        assert!(book.manuscript(&c1_s1).is_err());
     }
     /*
 
@@ -692,6 +1972,7 @@ mod tests {
             name: "test_book".to_string(),
             location: &path,
             genre: Genre::Fantasy,
+            template: None,
         });
 
         new_book(req).unwrap();
@@ -1,43 +1,319 @@
-use crate::book::{Book, CompileBookRequest};
+use self::blocks::Block;
+use self::builds::{copy_output, output_dir, output_name, Build};
+use self::cache::RenderCache;
+use crate::book::{Author, Book, BookMeta, Chapter, CompileBookRequest, CompileOptions, Section};
 use crate::error;
-use actix::{Actor, Addr, Handler, Message, SyncContext};
+use crate::vcs::BookRepo;
+use actix::{Actor, Addr, Handler, SyncContext};
+use git2::Oid;
 use std::fs;
+use std::path::PathBuf;
+use tempdir::TempDir;
+
+mod blocks;
+mod builds;
+mod cache;
+mod docx;
+mod epub;
+mod html;
+mod jobs;
+mod latex;
+mod layout;
+mod manuscript;
+mod matter;
+mod pdf;
+mod presets;
+mod render;
+mod renderer;
+mod themes;
+mod typography;
+mod worker;
+
+pub use self::builds::{download_build, get_compile_history};
+pub use self::cache::clear_compile_cache;
+pub use self::jobs::{
+    cancel_compile_job, get_compile_artifact, get_compile_job, get_compile_jobs, CompileJob, Jobs,
+};
+pub use self::latex::LatexOptions;
+pub use self::layout::Layout;
+pub use self::manuscript::ManuscriptOptions;
+pub use self::matter::Matter;
+pub use self::presets::{get_compile_presets, run_compile_preset, save_compile_preset};
+pub use self::render::Markup;
+pub use self::renderer::{get_capabilities, Renderers};
+pub use self::themes::{get_themes, Theme};
+pub use self::typography::Locale;
+pub use self::worker::{run_render_worker, RenderConfig, RENDER_WORKER_FLAG};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Pdf,
+    Epub,
+    /// The whole book in `html/index.html`
+    Html,
+    /// An index page and one page per chapter in `html/`
+    Site,
+    Docx,
+    /// A latex project in `latex/`, built by the author
+    Latex,
+    /// A Word document in standard manuscript format, for submissions
+    Manuscript,
+}
+
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 7] = [
+        OutputFormat::Pdf,
+        OutputFormat::Epub,
+        OutputFormat::Html,
+        OutputFormat::Site,
+        OutputFormat::Docx,
+        OutputFormat::Latex,
+        OutputFormat::Manuscript,
+    ];
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        OutputFormat::Pdf
+    }
+}
+
+/// Everything the output formats need to know about the book being compiled.
+pub struct Document {
+    pub id: String,
+    /// Directory of the book, paths of images are relative to it
+    pub location: PathBuf,
+    pub meta: BookMeta,
+    pub authors: Vec<String>,
+    pub chapters: Vec<Chapter>,
+    pub markup: Markup,
+    pub chapter_headings: bool,
+    pub matter: Matter,
+    /// Year of the copyright notice, from the book metadata or the compiled commit
+    pub year: Option<i32>,
+    pub theme: Option<Theme>,
+    /// Locale of the curly quotes, dashes and ellipses, none to keep the text as it was typed
+    pub typography: Option<Locale>,
+    pub cache: RenderCache,
+}
+
+//tests start from an untitled document without chapters and change what they need
+#[cfg(test)]
+impl Default for Document {
+    fn default() -> Self {
+        Document {
+            id: String::new(),
+            location: PathBuf::new(),
+            meta: BookMeta::new(""),
+            authors: Vec::new(),
+            chapters: Vec::new(),
+            markup: Markup::default(),
+            chapter_headings: false,
+            matter: Matter::default(),
+            year: None,
+            theme: None,
+            typography: None,
+            cache: RenderCache::default(),
+        }
+    }
+}
+
+impl Document {
+    fn new(book: &Book, options: &CompileOptions) -> Result<Self, error::MyError> {
+        let ids = match options.root {
+            Some(ref root) => book.manuscript(root)?,
+            None if options.in_binder_order => book.in_binder_order(&options.ids),
+            None => options.ids.clone(),
+        };
+        let chapters = book.chapters(&ids)?;
+
+        //books without authors in their metadata are credited to the configured author
+        let mut authors = book.meta().authors.clone();
+        if authors.is_empty() {
+            if let Ok(author) = Author::read_from_disk() {
+                authors.push(author.name);
+            }
+        }
+
+        let theme = match options.theme {
+            Some(ref name) => Some(Theme::find(book.location(), name)?),
+            None => None,
+        };
+
+        let typography = if options.typography {
+            let language = book.meta().language.as_ref().map(String::as_str);
+            Some(
+                options
+                    .typography_locale
+                    .unwrap_or_else(|| Locale::of_language(language.unwrap_or("en"))),
+            )
+        } else {
+            None
+        };
+
+        Ok(Document {
+            id: book.id().to_string(),
+            location: book.location().to_path_buf(),
+            meta: book.meta().clone(),
+            authors,
+            chapters,
+            markup: options.markup,
+            chapter_headings: options.chapter_headings.unwrap_or(options.root.is_some()),
+            matter: options.matter.clone(),
+            year: book.meta().copyright_year,
+            theme,
+            typography,
+            cache: RenderCache::new(book.location()),
+        })
+    }
+
+    /// Html of a section, only rendered again when its content changed.
+    pub fn section_html(&self, section: &Section) -> String {
+        self.cache
+            .html(&section.content, self.markup, self.typography)
+    }
+
+    pub fn section_blocks(&self, section: &Section) -> Vec<Block> {
+        self.cache
+            .blocks(&section.content, self.markup, self.typography)
+    }
+
+    pub fn language(&self) -> &str {
+        self.meta
+            .language
+            .as_ref()
+            .map(String::as_str)
+            .unwrap_or("en")
+    }
+}
 
 pub struct AppState {
     pub compiler: Addr<BookCompiler>,
+    pub jobs: Jobs,
+    pub renderers: Renderers,
 }
 
+/// Runs compile jobs, pdfs are rendered in separate worker processes so a crash in the
+/// renderer only fails the job.
 pub struct BookCompiler {
-    pub pdf_app: wkhtmltopdf::PdfApplication,
+    pub jobs: Jobs,
+    pub renderers: Renderers,
 }
 
 impl Actor for BookCompiler {
     type Context = SyncContext<Self>;
 }
 
-impl Message for CompileBookRequest {
-    type Result = Result<(), error::MyError>;
+impl BookCompiler {
+    /// Writes the output of `msg` into a new build of the book and returns its path.
+    fn compile(&mut self, id: &str, msg: &CompileBookRequest) -> Result<PathBuf, error::MyError> {
+        self.jobs.progress(id, 0.0, "Opening book")?;
+        //a revision is exported outside the book and removed once the compile is done
+        let mut export = None;
+        let (book, commit) = match msg.revision {
+            Some(ref revision) => {
+                let dir = export.get_or_insert(TempDir::new("collabook_revision")?);
+                let (book, commit) = Book::open_revision(&msg.location, revision, dir.path())?;
+                (book, Some(commit))
+            }
+            None => (Book::open(msg.location.as_ref())?, None),
+        };
+        let options = &msg.options;
+        let mut doc = Document::new(&book, options)?;
+        //sections rendered from any revision are shared with the ones of the working tree
+        doc.cache = RenderCache::new(&msg.location);
+        let output_dir = match msg.output_dir {
+            Some(ref dir) => Some(output_dir(&msg.location, dir)?),
+            None => None,
+        };
+        let mut build = Build::start(&msg.location, options.format, commit)?;
+        //the year of the commit, so compiling a tagged draft again later gives the same book
+        if doc.year.is_none() {
+            doc.year = build
+                .commit
+                .as_ref()
+                .and_then(|commit| Oid::from_str(commit).ok())
+                .and_then(|oid| {
+                    let repo = BookRepo::from_location(&msg.location).ok()?;
+                    repo.commit_year(oid).ok()
+                });
+        }
+        let target = build.dir(&msg.location);
+        let name = msg.filename.as_ref().map(String::as_str);
+
+        self.jobs.progress(id, 0.3, "Rendering")?;
+        let artifact = match options.format {
+            OutputFormat::Pdf => {
+                let task = worker::RenderTask {
+                    html: render::print_document(&doc, &options.layout),
+                    layout: options.layout.clone(),
+                    title: doc.meta.title.clone(),
+                    author: doc.authors.join(", "),
+                    output: target.join(output_name(name, "pdf")?),
+                };
+                let renderer = self
+                    .renderers
+                    .get(options.renderer.as_ref().map(String::as_str))?;
+                self.jobs.progress(id, 0.5, "Rendering pdf")?;
+                renderer.render(&doc, &task, &|| self.jobs.is_cancelled(id))?;
+                task.output
+            }
+            OutputFormat::Epub => {
+                let path = target.join(output_name(name, "epub")?);
+                epub::write_epub(&doc, fs::File::create(&path)?)?;
+                path
+            }
+            OutputFormat::Docx => {
+                let path = target.join(output_name(name, "docx")?);
+                docx::write_docx(&doc, options.synopsis_comments, fs::File::create(&path)?)?;
+                path
+            }
+            OutputFormat::Manuscript => {
+                let path = target.join(output_name(name, "docx")?);
+                //the contact details on the title page come from the author config
+                let author = Author::read_from_disk().ok();
+                manuscript::write_manuscript(
+                    &doc,
+                    &options.manuscript,
+                    author.as_ref(),
+                    fs::File::create(&path)?,
+                )?;
+                path
+            }
+            OutputFormat::Html => {
+                let dir = target.join(output_name(name.or(Some("html")), "")?);
+                html::write_single(&doc, &dir)?;
+                dir.join("index.html")
+            }
+            OutputFormat::Latex => {
+                let dir = target.join(output_name(name.or(Some("latex")), "")?);
+                latex::write_latex(&doc, &options.latex, &dir)?;
+                dir
+            }
+            OutputFormat::Site => {
+                let dir = target.join(output_name(name.or(Some("html")), "")?);
+                html::write_site(&doc, &dir)?;
+                dir
+            }
+        };
+        build.artifact = artifact.clone();
+        build.save(&msg.location)?;
+        match output_dir {
+            Some(dir) => {
+                fs::create_dir_all(&dir)?;
+                copy_output(&target, &artifact, &dir)
+            }
+            None => Ok(artifact),
+        }
+    }
 }
 
-impl Handler<CompileBookRequest> for BookCompiler {
-    type Result = Result<(), error::MyError>;
-
-    fn handle(&mut self, msg: CompileBookRequest, _: &mut Self::Context) -> Self::Result {
-        let book = Book::open(msg.location.as_ref())?;
-        let content = book.combine_content(msg.ids.as_slice())?;
-
-        let mut pdfout = self
-            .pdf_app
-            .builder()
-            .orientation(wkhtmltopdf::Orientation::Landscape)
-            .margin(wkhtmltopdf::Size::Millimeters(10))
-            .title("Book")
-            .build_from_html(&content)?;
-
-        fs::create_dir_all(msg.location.join("target"))?;
-        let path = msg.location.join("target/book.pdf");
-        pdfout.save(path)?;
-        Ok(())
+impl Handler<CompileJob> for BookCompiler {
+    type Result = ();
+
+    fn handle(&mut self, job: CompileJob, _: &mut Self::Context) -> Self::Result {
+        let result = self.compile(&job.id, &job.request);
+        self.jobs.finish(&job.id, result);
     }
 }
 
@@ -0,0 +1,314 @@
+use super::typography::{self, Locale};
+use super::Markup;
+use pulldown_cmark::{Event, Parser, Tag};
+
+/// Shown between sections of a chapter and for thematic breaks inside a section.
+pub const SCENE_BREAK: &str = "#";
+
+/// A run of text with one style. A `\n` inside `text` is a line break.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Span {
+    pub text: String,
+    pub emphasis: bool,
+    pub strong: bool,
+    pub code: bool,
+}
+
+/// Block level structure of a section, for output formats that don't take html.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Block {
+    Heading(i32, Vec<Span>),
+    Paragraph(Vec<Span>),
+    Quote(Vec<Span>),
+    /// A list item, numbered items carry their number.
+    Item(Option<usize>, Vec<Span>),
+    Code(String),
+    Rule,
+    /// An image on its own line with its path and alternative text.
+    Image(String, String),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Heading(i32),
+    Paragraph,
+    Quote,
+    Item(Option<usize>),
+}
+
+#[derive(Default)]
+struct Builder {
+    blocks: Vec<Block>,
+    current: Option<(Kind, Vec<Span>)>,
+    style: Span,
+    quotes: usize,
+    lists: Vec<Option<usize>>,
+    code: Option<String>,
+    //path and alternative text of the image being read, and the block it interrupted
+    image: Option<(String, String, Option<Kind>)>,
+}
+
+impl Builder {
+    fn begin(&mut self, kind: Kind) {
+        self.end();
+        self.current = Some((kind, Vec::new()));
+    }
+
+    fn end(&mut self) {
+        if let Some((kind, spans)) = self.current.take() {
+            //an image can leave an empty paragraph behind
+            if kind == Kind::Paragraph && spans.is_empty() {
+                return;
+            }
+            self.blocks.push(match kind {
+                Kind::Heading(level) => Block::Heading(level, spans),
+                Kind::Paragraph => Block::Paragraph(spans),
+                Kind::Quote => Block::Quote(spans),
+                Kind::Item(number) => Block::Item(number, spans),
+            });
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        if let Some(ref mut code) = self.code {
+            code.push_str(text);
+            return;
+        }
+        if let Some((_, ref mut alt, _)) = self.image {
+            alt.push_str(text);
+            return;
+        }
+        if self.current.is_none() {
+            let kind = if self.quotes > 0 {
+                Kind::Quote
+            } else {
+                Kind::Paragraph
+            };
+            self.current = Some((kind, Vec::new()));
+        }
+        let style = &self.style;
+        let spans = &mut self.current.as_mut().unwrap().1;
+        //neighbouring text with the same style is merged into one span
+        match spans.last_mut() {
+            Some(ref mut last)
+                if last.emphasis == style.emphasis
+                    && last.strong == style.strong
+                    && last.code == style.code =>
+            {
+                last.text.push_str(text)
+            }
+            _ => spans.push(Span {
+                text: text.to_string(),
+                ..style.clone()
+            }),
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => match self.current {
+                //paragraphs of loose list items belong to the item
+                Some((Kind::Item(_), ref spans)) if spans.is_empty() => {}
+                _ if self.quotes > 0 => self.begin(Kind::Quote),
+                _ => self.begin(Kind::Paragraph),
+            },
+            Tag::Header(level) => self.begin(Kind::Heading(level)),
+            Tag::BlockQuote => {
+                self.end();
+                self.quotes += 1;
+            }
+            Tag::CodeBlock(_) => {
+                self.end();
+                self.code = Some(String::new());
+            }
+            Tag::List(start) => {
+                self.end();
+                self.lists.push(start);
+            }
+            Tag::Item => {
+                let number = match self.lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        Some(*n - 1)
+                    }
+                    _ => None,
+                };
+                self.begin(Kind::Item(number));
+            }
+            Tag::Rule => {
+                self.end();
+                self.blocks.push(Block::Rule);
+            }
+            Tag::Image(src, _) => {
+                let interrupted = self.current.as_ref().map(|(kind, _)| *kind);
+                self.end();
+                self.image = Some((src.to_string(), String::new(), interrupted));
+            }
+            Tag::Emphasis => self.style.emphasis = true,
+            Tag::Strong => self.style.strong = true,
+            Tag::Code => self.style.code = true,
+            Tag::TableCell => {
+                if self.current.as_ref().map_or(false, |c| !c.1.is_empty()) {
+                    self.text("\t");
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => match self.current {
+                Some((Kind::Item(_), _)) => {}
+                _ => self.end(),
+            },
+            Tag::Header(_) | Tag::Item | Tag::TableRow | Tag::TableHead => self.end(),
+            Tag::BlockQuote => {
+                self.end();
+                self.quotes = self.quotes.saturating_sub(1);
+            }
+            Tag::CodeBlock(_) => {
+                if let Some(code) = self.code.take() {
+                    self.blocks.push(Block::Code(code));
+                }
+            }
+            Tag::List(_) => {
+                self.end();
+                self.lists.pop();
+            }
+            Tag::Image(..) => {
+                if let Some((src, alt, interrupted)) = self.image.take() {
+                    self.blocks.push(Block::Image(src, alt));
+                    //text after the image continues in a new block of the same kind
+                    self.current = interrupted.map(|kind| (kind, Vec::new()));
+                }
+            }
+            Tag::Emphasis => self.style.emphasis = false,
+            Tag::Strong => self.style.strong = false,
+            Tag::Code => self.style.code = false,
+            _ => {}
+        }
+    }
+}
+
+fn markdown_blocks(content: &str, typography: Option<Locale>) -> Vec<Block> {
+    let mut builder = Builder::default();
+    for event in typography::markdown_events(Parser::new(content), typography) {
+        match event {
+            Event::Start(tag) => builder.start(tag),
+            Event::End(tag) => builder.finish(tag),
+            Event::Text(text) | Event::Html(text) | Event::InlineHtml(text) => builder.text(&text),
+            Event::FootnoteReference(name) => builder.text(&format!("[{}]", name)),
+            Event::SoftBreak => builder.text(" "),
+            Event::HardBreak => builder.text("\n"),
+        }
+    }
+    builder.end();
+    builder.blocks
+}
+
+fn plain_blocks(content: &str, typography: Option<Locale>) -> Vec<Block> {
+    let content = typography::plain_text(&content.replace("\r\n", "\n"), typography);
+    content
+        .split("\n\n")
+        .map(|paragraph| {
+            paragraph
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .filter(|paragraph| !paragraph.is_empty())
+        .map(|text| {
+            Block::Paragraph(vec![Span {
+                text,
+                ..Span::default()
+            }])
+        })
+        .collect()
+}
+
+pub fn section_blocks(content: &str, markup: Markup, typography: Option<Locale>) -> Vec<Block> {
+    match markup {
+        Markup::Markdown => markdown_blocks(content, typography),
+        Markup::Plain => plain_blocks(content, typography),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(text: &str) -> Span {
+        Span {
+            text: text.to_string(),
+            ..Span::default()
+        }
+    }
+
+    #[test]
+    fn markdown_is_split_into_blocks() {
+        let blocks = section_blocks(
+            "# Night\n\nIt was *dark*\nand cold.\n\n> quoted\n\n1. one\n2. two\n\n---\n\n    code\n",
+            Markup::Markdown,
+            None,
+        );
+        assert_eq!(
+            blocks,
+            vec![
+                Block::Heading(1, vec![span("Night")]),
+                Block::Paragraph(vec![
+                    span("It was "),
+                    Span {
+                        emphasis: true,
+                        ..span("dark")
+                    },
+                    span(" and cold."),
+                ]),
+                Block::Quote(vec![span("quoted")]),
+                Block::Item(Some(1), vec![span("one")]),
+                Block::Item(Some(2), vec![span("two")]),
+                Block::Rule,
+                Block::Code("code\n".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn plain_text_keeps_line_breaks() {
+        let blocks = section_blocks("Tom & Jerry\nsaid *hi*\n\n\nThe end", Markup::Plain, None);
+        assert_eq!(
+            blocks,
+            vec![
+                Block::Paragraph(vec![span("Tom & Jerry\nsaid *hi*")]),
+                Block::Paragraph(vec![span("The end")]),
+            ]
+        );
+
+        let blocks = section_blocks("\"Hi\"\n'bye'", Markup::Plain, Some(Locale::German));
+        assert_eq!(
+            blocks,
+            vec![Block::Paragraph(vec![span(
+                "\u{201e}Hi\u{201c}\n\u{201a}bye\u{2018}"
+            )])]
+        );
+    }
+
+    #[test]
+    fn images_get_their_own_block() {
+        let blocks = section_blocks(
+            "![The *old* map](images/map.png)\n\nSee ![x](x.png) here",
+            Markup::Markdown,
+            None,
+        );
+        assert_eq!(
+            blocks,
+            vec![
+                Block::Image("images/map.png".to_string(), "The old map".to_string()),
+                Block::Paragraph(vec![span("See ")]),
+                Block::Image("x.png".to_string(), "x".to_string()),
+                Block::Paragraph(vec![span(" here")]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,332 @@
+use super::OutputFormat;
+use crate::error::MyError;
+use crate::vcs::BookRepo;
+use actix_web::fs::NamedFile;
+use actix_web::{HttpResponse, Json, Responder};
+use chrono::Utc;
+use git2::Oid;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use walkdir::WalkDir;
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+/// A compile of a book, recorded in `target/builds/<id>/build.json` so earlier builds aren't
+/// overwritten by later ones.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Build {
+    /// Time of the build and the commit the book was at, e.g. `20190412T093000Z-1a2b3c4`
+    pub id: String,
+    pub created: String,
+    /// Full id of the commit, none for books without commits
+    pub commit: Option<String>,
+    pub format: OutputFormat,
+    /// The compiled file, or directory for multi file formats
+    pub artifact: PathBuf,
+}
+
+fn builds_dir(location: &Path) -> PathBuf {
+    location.join("target/builds")
+}
+
+impl Build {
+    /// Creates the directory of a new build, the artifact is filled in once it is written.
+    /// `commit` is the one compiled, HEAD when the working tree was.
+    pub fn start(
+        location: &Path,
+        format: OutputFormat,
+        commit: Option<Oid>,
+    ) -> Result<Self, MyError> {
+        let now = Utc::now();
+        let commit = commit
+            .or_else(|| {
+                BookRepo::from_location(location)
+                    .ok()
+                    .and_then(|repo| repo.head().ok().and_then(|head| head.target()))
+            })
+            .map(|oid| oid.to_string());
+        let key = format!(
+            "{}-{}",
+            now.format("%Y%m%dT%H%M%SZ"),
+            commit.as_ref().map(|c| &c[..7]).unwrap_or("uncommitted")
+        );
+
+        //builds started within the same second get a counter
+        let mut id = key.clone();
+        let mut n = 1;
+        while builds_dir(location).join(&id).exists() {
+            n += 1;
+            id = format!("{}-{}", key, n);
+        }
+        let build = Build {
+            id,
+            created: now.to_rfc3339(),
+            commit,
+            format,
+            artifact: PathBuf::new(),
+        };
+        fs::create_dir_all(build.dir(location))?;
+        Ok(build)
+    }
+
+    pub fn dir(&self, location: &Path) -> PathBuf {
+        builds_dir(location).join(&self.id)
+    }
+
+    pub fn save(&self, location: &Path) -> Result<(), MyError> {
+        fs::write(
+            self.dir(location).join("build.json"),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        Ok(())
+    }
+
+    /// Finished builds of the book, newest first.
+    pub fn history(location: &Path) -> Result<Vec<Build>, MyError> {
+        let dir = builds_dir(location);
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut builds = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path().join("build.json");
+            //builds that failed have no build.json
+            if path.exists() {
+                builds.push(serde_json::from_str::<Build>(&fs::read_to_string(path)?)?);
+            }
+        }
+        builds.sort_by(|a, b| b.created.cmp(&a.created).then(b.id.cmp(&a.id)));
+        Ok(builds)
+    }
+
+    pub fn get(location: &Path, id: &str) -> Result<Build, MyError> {
+        Build::history(location)?
+            .into_iter()
+            .find(|build| build.id == id)
+            .ok_or_else(|| MyError(format!("Build {} not found", id)))
+    }
+}
+
+/// `name` with the extension of the format, `book` when no name was requested.
+pub fn output_name(name: Option<&str>, extension: &str) -> Result<String, MyError> {
+    let name = name.map(str::trim).unwrap_or("book");
+    if name.is_empty() || Path::new(name).file_name() != Some(OsStr::new(name)) {
+        Err("Output filename must be a file name without a directory")?
+    }
+    if extension.is_empty() || name.ends_with(&format!(".{}", extension)) {
+        Ok(name.to_string())
+    } else {
+        Ok(format!("{}.{}", name, extension))
+    }
+}
+
+/// `dir` inside `target/exports` of the book at `location`, which isn't read as part of the
+/// manuscript.
+pub fn output_dir(location: &Path, dir: &Path) -> Result<PathBuf, MyError> {
+    let inside = dir.components().all(|component| match component {
+        Component::Normal(_) | Component::CurDir => true,
+        _ => false,
+    });
+    let named = dir.components().any(|component| match component {
+        Component::Normal(_) => true,
+        _ => false,
+    });
+    if !inside || !named {
+        Err("Output directory must be a relative path like `drafts`")?
+    }
+    Ok(location.join("target/exports").join(dir))
+}
+
+/// Copies the output in `build_dir` that holds `artifact` into `dir` and returns the path of the
+/// copied artifact. The build keeps its own copy so later compiles don't change its history.
+pub fn copy_output(build_dir: &Path, artifact: &Path, dir: &Path) -> Result<PathBuf, MyError> {
+    let relative = artifact.strip_prefix(build_dir)?;
+    //the file or directory the format wrote, an html artifact is the index.html inside it
+    let output = match relative.components().next() {
+        Some(first) => build_dir.join(first),
+        None => Err("Artifact is not part of the build")?,
+    };
+    for entry in WalkDir::new(&output) {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let to = dir.join(entry.path().strip_prefix(build_dir)?);
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(to)?;
+        } else {
+            fs::copy(entry.path(), to)?;
+        }
+    }
+    Ok(dir.join(relative))
+}
+
+// multi file outputs are downloaded as a zip written next to the directory
+fn zip_dir(dir: &Path) -> Result<PathBuf, MyError> {
+    let path = dir.with_extension("zip");
+    let mut zip = ZipWriter::new(fs::File::create(&path)?);
+    for entry in WalkDir::new(dir).sort_by(|a, b| a.file_name().cmp(b.file_name())) {
+        let entry = entry.map_err(|e| e.to_string())?;
+        if entry.file_type().is_file() {
+            let name = entry.path().strip_prefix(dir)?.to_string_lossy();
+            zip.start_file(name.replace("\\", "/"), FileOptions::default())?;
+            zip.write_all(&fs::read(entry.path())?)?;
+        }
+    }
+    zip.finish()?;
+    Ok(path)
+}
+
+fn content_type(path: &Path) -> mime::Mime {
+    let content_type = match path.extension().and_then(|e| e.to_str()) {
+        Some("pdf") => "application/pdf",
+        Some("epub") => "application/epub+zip",
+        Some("docx") => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        Some("html") => "text/html; charset=utf-8",
+        Some("zip") => "application/zip",
+        _ => "application/octet-stream",
+    };
+    content_type
+        .parse()
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM)
+}
+
+/// Streams `artifact` with the content type of its format, directories are zipped first.
+pub fn download(artifact: &Path) -> Result<NamedFile, MyError> {
+    let path = if artifact.is_dir() {
+        zip_dir(artifact)?
+    } else {
+        artifact.to_path_buf()
+    };
+    let content_type = content_type(&path);
+    Ok(NamedFile::open(path)?.set_content_type(content_type))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CompileHistoryRequest {
+    location: PathBuf,
+}
+
+pub fn get_compile_history(info: Json<CompileHistoryRequest>) -> Result<impl Responder, MyError> {
+    Ok(HttpResponse::Ok().json(Build::history(&info.location)?))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DownloadBuildRequest {
+    location: PathBuf,
+    id: String,
+}
+
+pub fn download_build(info: Json<DownloadBuildRequest>) -> Result<NamedFile, MyError> {
+    download(&Build::get(&info.location, &info.id)?.artifact)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::{new_book, Book};
+    use crate::bookcompiler::{BookCompiler, Jobs, Renderers};
+    use tempdir::TempDir;
+
+    #[test]
+    fn builds_are_kept_and_listed_newest_first() {
+        let temp_dir = TempDir::new("test_builds").unwrap();
+        let location = temp_dir.path();
+
+        let mut first = Build::start(location, OutputFormat::Pdf, None).unwrap();
+        assert!(first.id.ends_with("-uncommitted"));
+        first.artifact = first.dir(location).join("book.pdf");
+        first.save(location).unwrap();
+        let mut second = Build::start(location, OutputFormat::Epub, None).unwrap();
+        assert_ne!(first.id, second.id);
+        second.artifact = second.dir(location).join("book.epub");
+        second.save(location).unwrap();
+        //a build that never finished
+        Build::start(location, OutputFormat::Pdf, None).unwrap();
+
+        let history = Build::history(location).unwrap();
+        assert_eq!(history, vec![second, first.clone()]);
+        assert_eq!(Build::get(location, &first.id).unwrap(), first);
+        assert!(Build::get(location, "nope").is_err());
+    }
+
+    #[test]
+    fn output_names_get_the_format_extension() {
+        assert_eq!(output_name(None, "pdf").unwrap(), "book.pdf");
+        assert_eq!(output_name(Some("draft 2"), "pdf").unwrap(), "draft 2.pdf");
+        assert_eq!(output_name(Some("draft.pdf"), "pdf").unwrap(), "draft.pdf");
+        assert_eq!(output_name(Some("site"), "").unwrap(), "site");
+        assert!(output_name(Some("../draft"), "pdf").is_err());
+        assert!(output_name(Some(" "), "pdf").is_err());
+    }
+
+    #[test]
+    fn output_dirs_stay_inside_the_book() {
+        let location = Path::new("/books/novel");
+        assert_eq!(
+            output_dir(location, Path::new("drafts/./beta")).unwrap(),
+            location.join("target/exports/drafts/./beta")
+        );
+        assert!(output_dir(location, Path::new("../exports")).is_err());
+        assert!(output_dir(location, Path::new("exports/../../x")).is_err());
+        assert!(output_dir(location, Path::new("/tmp")).is_err());
+        assert!(output_dir(location, Path::new("")).is_err());
+        assert!(output_dir(location, Path::new(".")).is_err());
+    }
+
+    #[test]
+    fn books_still_open_after_compiling_into_an_output_dir() {
+        let temp_dir = TempDir::new("test_output_dir").unwrap();
+        let path = temp_dir.path().join("test_book");
+        let request = serde_json::json!({
+            "location": path,
+            "name": "test_book",
+            "genre": "Fantasy",
+        });
+        new_book::<PathBuf>(Json(serde_json::from_value(request).unwrap())).unwrap();
+        let book = Book::open(&path).unwrap();
+
+        let jobs = Jobs::default();
+        let job = jobs.queue(&path, OutputFormat::Docx);
+        let mut compiler = BookCompiler {
+            jobs,
+            renderers: Renderers::default(),
+        };
+        let request = serde_json::json!({
+            "location": path,
+            "output_dir": "drafts",
+            "root": book.id(),
+            "format": "Docx",
+        });
+        let artifact = compiler
+            .compile(&job.id, &serde_json::from_value(request).unwrap())
+            .unwrap();
+        assert_eq!(artifact, path.join("target/exports/drafts/book.docx"));
+
+        Book::open(&path).unwrap();
+    }
+
+    #[test]
+    fn outputs_are_copied_and_the_build_keeps_its_own() {
+        let temp_dir = TempDir::new("test_copy_output").unwrap();
+        let build_dir = temp_dir.path().join("target/builds/1");
+        fs::create_dir_all(build_dir.join("html/images")).unwrap();
+        fs::write(build_dir.join("html/index.html"), "<p>one</p>").unwrap();
+        fs::write(build_dir.join("html/images/map.png"), "png").unwrap();
+        fs::write(build_dir.join("build.json"), "{}").unwrap();
+        let exports = temp_dir.path().join("exports");
+        fs::create_dir_all(&exports).unwrap();
+
+        let copied = copy_output(&build_dir, &build_dir.join("html/index.html"), &exports).unwrap();
+        assert_eq!(copied, exports.join("html/index.html"));
+        assert_eq!(fs::read_to_string(&copied).unwrap(), "<p>one</p>");
+        assert!(exports.join("html/images/map.png").exists());
+        assert!(!exports.join("build.json").exists());
+
+        //a later compile into the same directory leaves the build alone
+        fs::write(&copied, "<p>two</p>").unwrap();
+        assert_eq!(
+            fs::read_to_string(build_dir.join("html/index.html")).unwrap(),
+            "<p>one</p>"
+        );
+    }
+}
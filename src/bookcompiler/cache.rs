@@ -0,0 +1,182 @@
+use super::blocks::{self, Block};
+use super::render::{self, Markup};
+use super::typography::Locale;
+use crate::error::MyError;
+use actix_web::{HttpResponse, Json, Responder};
+use git2::{ObjectType, Oid};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+fn cache_dir(location: &Path) -> PathBuf {
+    location.join("target/cache")
+}
+
+/// Rendered sections of a book in `target/cache/`, keyed by the git blob id of their content.
+/// A compile only renders the sections that changed since the last one, a cache without
+/// directory renders everything.
+#[derive(Debug, Clone, Default)]
+pub struct RenderCache {
+    dir: Option<PathBuf>,
+}
+
+impl RenderCache {
+    //rendering changes between versions, each one gets its own entries
+    pub fn new(location: &Path) -> Self {
+        RenderCache {
+            dir: Some(cache_dir(location).join(env!("CARGO_PKG_VERSION"))),
+        }
+    }
+
+    fn path(&self, kind: &str, content: &str) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+        let oid = Oid::hash_object(ObjectType::Blob, content.as_bytes()).ok()?;
+        Some(dir.join(kind).join(oid.to_string()))
+    }
+
+    fn get_or_render<T, F>(&self, kind: &str, content: &str, render: F) -> T
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> T,
+    {
+        let path = match self.path(kind, content) {
+            Some(path) => path,
+            None => return render(),
+        };
+        if let Some(cached) = fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        {
+            return cached;
+        }
+        let rendered = render();
+        //a section that couldn't be cached is rendered again next time, nothing more
+        if let Err(e) = store(&path, &rendered) {
+            warn!("couldn't cache {}: {}", path.display(), e);
+        }
+        rendered
+    }
+
+    pub fn html(&self, content: &str, markup: Markup, typography: Option<Locale>) -> String {
+        let kind = kind("html", markup, typography);
+        self.get_or_render(&kind, content, || {
+            render::section_to_html(content, markup, typography)
+        })
+    }
+
+    pub fn blocks(&self, content: &str, markup: Markup, typography: Option<Locale>) -> Vec<Block> {
+        let kind = kind("blocks", markup, typography);
+        self.get_or_render(&kind, content, || {
+            blocks::section_blocks(content, markup, typography)
+        })
+    }
+
+    pub fn clear(location: &Path) -> Result<(), MyError> {
+        let dir = cache_dir(location);
+        if dir.exists() {
+            fs::remove_dir_all(dir)?;
+        }
+        Ok(())
+    }
+}
+
+// directory of the entries rendered with the same markup and typography, e.g. `html-markdown-french`
+fn kind(output: &str, markup: Markup, typography: Option<Locale>) -> String {
+    let markup = match markup {
+        Markup::Markdown => "markdown",
+        Markup::Plain => "plain",
+    };
+    match typography {
+        Some(locale) => format!(
+            "{}-{}-{}",
+            output,
+            markup,
+            format!("{:?}", locale).to_lowercase()
+        ),
+        None => format!("{}-{}", output, markup),
+    }
+}
+
+// written next to its place and renamed, compiles running at once never read half an entry
+fn store<T: Serialize>(path: &Path, value: &T) -> Result<(), MyError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let temp = path.with_extension(format!("{}.tmp", Uuid::new_v4()));
+    fs::write(&temp, serde_json::to_vec(value)?)?;
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ClearCompileCacheRequest {
+    location: PathBuf,
+}
+
+pub fn clear_compile_cache(
+    info: Json<ClearCompileCacheRequest>,
+) -> Result<impl Responder, MyError> {
+    RenderCache::clear(&info.location)?;
+    Ok(HttpResponse::Ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use tempdir::TempDir;
+
+    #[test]
+    fn sections_are_rendered_once_per_content() {
+        let temp_dir = TempDir::new("test_cache").unwrap();
+        let cache = RenderCache::new(temp_dir.path());
+        let renders = Cell::new(0);
+        let render = |content: &str| {
+            cache.get_or_render("html-markdown", content, || {
+                renders.set(renders.get() + 1);
+                render::section_to_html(content, Markup::Markdown, None)
+            })
+        };
+
+        assert_eq!(render("*one*"), "<p><em>one</em></p>\n");
+        assert_eq!(render("*one*"), "<p><em>one</em></p>\n");
+        assert_eq!(renders.get(), 1);
+        render("*two*");
+        assert_eq!(renders.get(), 2);
+
+        //entries are named like the blob git stores for the same content
+        let oid = Oid::hash_object(ObjectType::Blob, b"*one*").unwrap();
+        let path = cache_dir(temp_dir.path())
+            .join(env!("CARGO_PKG_VERSION"))
+            .join("html-markdown")
+            .join(oid.to_string());
+        assert!(path.is_file());
+
+        assert_eq!(
+            cache.blocks("*one*", Markup::Plain, None),
+            cache.blocks("*one*", Markup::Plain, None)
+        );
+        //typography is part of the kind, not of the content
+        assert_eq!(
+            cache.html("'one'", Markup::Plain, Some(Locale::English)),
+            "<p>\u{2018}one\u{2019}</p>\n"
+        );
+        assert_eq!(
+            cache.html("'one'", Markup::Plain, None),
+            "<p>&#39;one&#39;</p>\n"
+        );
+        RenderCache::clear(temp_dir.path()).unwrap();
+        assert!(!cache_dir(temp_dir.path()).exists());
+        render("*one*");
+        assert_eq!(renders.get(), 3);
+    }
+
+    #[test]
+    fn cache_without_directory_renders_every_time() {
+        let cache = RenderCache::default();
+        assert_eq!(cache.html("one", Markup::Plain, None), "<p>one</p>\n");
+        assert_eq!(cache.path("html-plain", "one"), None);
+    }
+}
@@ -0,0 +1,446 @@
+use super::blocks::{section_blocks, Block, Span, SCENE_BREAK};
+use super::matter::{self, ABOUT_AUTHOR_TITLE, CONTENTS_TITLE};
+use super::render::escape_html;
+use super::Document;
+use crate::error::MyError;
+use chrono::Utc;
+use std::io::{Seek, Write};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+const CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">
+<Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>
+<Default Extension="xml" ContentType="application/xml"/>
+<Override PartName="/word/document.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml"/>
+<Override PartName="/word/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.styles+xml"/>
+<Override PartName="/word/settings.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.settings+xml"/>
+<Override PartName="/word/comments.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.comments+xml"/>
+<Override PartName="/docProps/core.xml" ContentType="application/vnd.openxmlformats-package.core-properties+xml"/>
+</Types>
+"#;
+
+pub const RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="word/document.xml"/>
+<Relationship Id="rId2" Type="http://schemas.openxmlformats.org/package/2006/relationships/metadata/core-properties" Target="docProps/core.xml"/>
+</Relationships>
+"#;
+
+const DOCUMENT_RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/>
+<Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/settings" Target="settings.xml"/>
+<Relationship Id="rId3" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/comments" Target="comments.xml"/>
+</Relationships>
+"#;
+
+// edits made by the editor show up as tracked changes by default
+const SETTINGS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:settings xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main">
+<w:trackRevisions/>
+</w:settings>
+"#;
+
+pub const PAGE_BREAK: &str = "<w:p><w:r><w:br w:type=\"page\"/></w:r></w:p>";
+
+// the entries and their page numbers are filled in by word, which updates dirty fields on open
+const TOC_FIELD: &str = r#"<w:p><w:r><w:fldChar w:fldCharType="begin" w:dirty="true"/></w:r><w:r><w:instrText xml:space="preserve"> TOC \o "1-1" \h </w:instrText></w:r><w:r><w:fldChar w:fldCharType="separate"/></w:r><w:r><w:t>Update the field to show the table of contents</w:t></w:r><w:r><w:fldChar w:fldCharType="end"/></w:r></w:p>"#;
+
+pub const W_NS: &str = "xmlns:w=\"http://schemas.openxmlformats.org/wordprocessingml/2006/main\"";
+
+fn styles() -> String {
+    let mut styles = String::from(
+        "<w:docDefaults><w:rPrDefault><w:rPr>\
+         <w:rFonts w:ascii=\"Times New Roman\" w:hAnsi=\"Times New Roman\"/>\
+         <w:sz w:val=\"24\"/></w:rPr></w:rPrDefault></w:docDefaults>\n\
+         <w:style w:type=\"paragraph\" w:default=\"1\" w:styleId=\"Normal\"><w:name w:val=\"Normal\"/>\
+         <w:pPr><w:spacing w:after=\"120\" w:line=\"360\" w:lineRule=\"auto\"/></w:pPr></w:style>\n\
+         <w:style w:type=\"paragraph\" w:styleId=\"Title\"><w:name w:val=\"Title\"/>\
+         <w:basedOn w:val=\"Normal\"/><w:pPr><w:jc w:val=\"center\"/></w:pPr>\
+         <w:rPr><w:sz w:val=\"56\"/></w:rPr></w:style>\n\
+         <w:style w:type=\"paragraph\" w:styleId=\"TOCHeading\"><w:name w:val=\"TOC Heading\"/>\
+         <w:basedOn w:val=\"Normal\"/><w:pPr><w:pageBreakBefore/><w:spacing w:before=\"240\" w:after=\"120\"/></w:pPr>\
+         <w:rPr><w:b/><w:sz w:val=\"36\"/></w:rPr></w:style>\n\
+         <w:style w:type=\"paragraph\" w:styleId=\"SceneBreak\"><w:name w:val=\"Scene Break\"/>\
+         <w:basedOn w:val=\"Normal\"/><w:pPr><w:jc w:val=\"center\"/></w:pPr></w:style>\n\
+         <w:style w:type=\"paragraph\" w:styleId=\"Quote\"><w:name w:val=\"Quote\"/>\
+         <w:basedOn w:val=\"Normal\"/><w:pPr><w:ind w:left=\"720\" w:right=\"720\"/></w:pPr>\
+         <w:rPr><w:i/></w:rPr></w:style>\n\
+         <w:style w:type=\"paragraph\" w:styleId=\"ListParagraph\"><w:name w:val=\"List Paragraph\"/>\
+         <w:basedOn w:val=\"Normal\"/><w:pPr><w:ind w:left=\"720\" w:hanging=\"360\"/></w:pPr></w:style>\n\
+         <w:style w:type=\"paragraph\" w:styleId=\"SourceCode\"><w:name w:val=\"Source Code\"/>\
+         <w:basedOn w:val=\"Normal\"/><w:pPr><w:spacing w:line=\"240\" w:lineRule=\"auto\"/></w:pPr>\
+         <w:rPr><w:rFonts w:ascii=\"Courier New\" w:hAnsi=\"Courier New\"/><w:sz w:val=\"20\"/></w:rPr></w:style>\n",
+    );
+    let sizes = [36, 32, 28, 26, 24, 24];
+    for (i, size) in sizes.iter().enumerate() {
+        //every chapter starts on a new page
+        let page_break = if i == 0 { "<w:pageBreakBefore/>" } else { "" };
+        styles.push_str(&format!(
+            "<w:style w:type=\"paragraph\" w:styleId=\"Heading{0}\"><w:name w:val=\"heading {0}\"/>\
+             <w:basedOn w:val=\"Normal\"/><w:next w:val=\"Normal\"/>\
+             <w:pPr><w:keepNext/>{1}<w:spacing w:before=\"240\" w:after=\"120\"/>\
+             <w:outlineLvl w:val=\"{2}\"/></w:pPr><w:rPr><w:b/><w:sz w:val=\"{3}\"/></w:rPr></w:style>\n",
+            i + 1,
+            page_break,
+            i,
+            size
+        ));
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<w:styles {}>\n{}</w:styles>\n",
+        W_NS, styles
+    )
+}
+
+pub fn core(doc: &Document) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+         <cp:coreProperties xmlns:cp=\"http://schemas.openxmlformats.org/package/2006/metadata/core-properties\" \
+         xmlns:dc=\"http://purl.org/dc/elements/1.1/\" xmlns:dcterms=\"http://purl.org/dc/terms/\" \
+         xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\">\n\
+         <dc:title>{}</dc:title>\n<dc:creator>{}</dc:creator>\n<dc:language>{}</dc:language>\n\
+         <dcterms:created xsi:type=\"dcterms:W3CDTF\">{}</dcterms:created>\n\
+         </cp:coreProperties>\n",
+        escape_html(&doc.meta.title),
+        escape_html(&doc.authors.join(", ")),
+        escape_html(doc.language()),
+        Utc::now().format("%Y-%m-%dT%H:%M:%SZ")
+    )
+}
+
+fn run(span: &Span) -> String {
+    //the schema wants the font before bold and italic
+    let mut props = String::new();
+    if span.code {
+        props.push_str("<w:rFonts w:ascii=\"Courier New\" w:hAnsi=\"Courier New\"/>");
+    }
+    if span.strong {
+        props.push_str("<w:b/>");
+    }
+    if span.emphasis {
+        props.push_str("<w:i/>");
+    }
+    let props = if props.is_empty() {
+        props
+    } else {
+        format!("<w:rPr>{}</w:rPr>", props)
+    };
+
+    let text = span
+        .text
+        .split('\n')
+        .map(|line| format!("<w:t xml:space=\"preserve\">{}</w:t>", escape_html(line)))
+        .collect::<Vec<_>>()
+        .join("<w:br/>");
+    format!("<w:r>{}{}</w:r>", props, text)
+}
+
+pub fn text(text: &str) -> Span {
+    Span {
+        text: text.to_string(),
+        ..Span::default()
+    }
+}
+
+pub fn paragraph(style: &str, spans: &[Span]) -> String {
+    let runs: String = spans.iter().map(run).collect();
+    format!(
+        "<w:p><w:pPr><w:pStyle w:val=\"{}\"/></w:pPr>{}</w:p>",
+        style, runs
+    )
+}
+
+// headings inside a section sit one level below the chapter heading
+pub fn block(block: &Block) -> String {
+    match block {
+        Block::Heading(level, spans) => {
+            paragraph(&format!("Heading{}", (level + 1).min(6).max(2)), spans)
+        }
+        Block::Paragraph(spans) => paragraph("Normal", spans),
+        Block::Quote(spans) => paragraph("Quote", spans),
+        Block::Item(number, spans) => {
+            let bullet = match number {
+                Some(n) => format!("{}.\t", n),
+                None => "\u{2022}\t".to_string(),
+            };
+            let mut item = vec![text(&bullet)];
+            item.extend_from_slice(spans);
+            paragraph("ListParagraph", &item)
+        }
+        Block::Code(code) => paragraph("SourceCode", &[text(code.trim_end_matches('\n'))]),
+        Block::Rule => paragraph("SceneBreak", &[text(SCENE_BREAK)]),
+        //images aren't packaged, their description stands in for them
+        Block::Image(_, alt) => paragraph(
+            "Normal",
+            &[Span {
+                emphasis: true,
+                ..text(alt)
+            }],
+        ),
+    }
+}
+
+// wraps the first paragraph of a section in a comment range, `comment` is the comment id
+fn anchor_comment(paragraph: &str, comment: usize) -> String {
+    let start = paragraph.find("</w:pPr>").map(|i| i + "</w:pPr>".len());
+    let end = paragraph.len() - "</w:p>".len();
+    match start {
+        Some(start) => format!(
+            "{}<w:commentRangeStart w:id=\"{2}\"/>{}<w:commentRangeEnd w:id=\"{2}\"/>\
+             <w:r><w:commentReference w:id=\"{2}\"/></w:r></w:p>",
+            &paragraph[..start],
+            &paragraph[start..end],
+            comment
+        ),
+        None => paragraph.to_string(),
+    }
+}
+
+fn comment(id: usize, author: &str, synopsis: &str) -> String {
+    let paragraphs: String = synopsis
+        .lines()
+        .map(|line| paragraph("Normal", &[text(line)]))
+        .collect();
+    format!(
+        "<w:comment w:id=\"{}\" w:author=\"{}\" w:initials=\"\">{}</w:comment>\n",
+        id,
+        escape_html(author),
+        paragraphs
+    )
+}
+
+// title, copyright and dedication each start a page, the contents heading breaks by its style
+fn front_matter(doc: &Document) -> String {
+    let mut body = String::new();
+    if doc.matter.title_page {
+        body.push_str(&paragraph("Title", &[text(&doc.meta.title)]));
+    }
+    if let Some(lines) = matter::copyright(doc) {
+        if !body.is_empty() {
+            body.push_str(PAGE_BREAK);
+        }
+        for line in lines {
+            body.push_str(&paragraph("Normal", &[text(&line)]));
+        }
+    }
+    if let Some(dedication) = matter::dedication(doc) {
+        if !body.is_empty() {
+            body.push_str(PAGE_BREAK);
+        }
+        for b in section_blocks(dedication, doc.markup, doc.typography) {
+            body.push_str(&block(&b));
+        }
+    }
+    if doc.matter.toc {
+        body.push_str(&paragraph("TOCHeading", &[text(CONTENTS_TITLE)]));
+        body.push_str(TOC_FIELD);
+    }
+    body
+}
+
+/// `word/document.xml` and `word/comments.xml` of the book. With `synopsis_comments` the
+/// synopsis of every section is attached as a comment to its first paragraph.
+fn document_and_comments(doc: &Document, synopsis_comments: bool) -> (String, String) {
+    let author = doc.authors.first().map(String::as_str).unwrap_or("");
+    let mut body = front_matter(doc);
+    let mut comments = String::new();
+    let mut comment_id = 0;
+
+    for chapter in &doc.chapters {
+        if doc.chapter_headings {
+            body.push_str(&paragraph("Heading1", &[text(&chapter.title)]));
+        } else {
+            body.push_str(PAGE_BREAK);
+        }
+        for (i, section) in chapter.sections.iter().enumerate() {
+            if i > 0 {
+                body.push_str(&paragraph("SceneBreak", &[text(SCENE_BREAK)]));
+            }
+            let mut paragraphs: Vec<String> =
+                doc.section_blocks(section).iter().map(block).collect();
+
+            if synopsis_comments && !section.synopsis.trim().is_empty() {
+                if paragraphs.is_empty() {
+                    paragraphs.push(paragraph("Normal", &[]));
+                }
+                paragraphs[0] = anchor_comment(&paragraphs[0], comment_id);
+                comments.push_str(&comment(comment_id, author, &section.synopsis));
+                comment_id += 1;
+            }
+            body.push_str(&paragraphs.concat());
+        }
+    }
+    if let Some(about) = matter::about_author(doc) {
+        body.push_str(&paragraph("Heading1", &[text(ABOUT_AUTHOR_TITLE)]));
+        for b in section_blocks(about, doc.markup, doc.typography) {
+            body.push_str(&block(&b));
+        }
+    }
+
+    let document = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+         <w:document {}>\n<w:body>\n{}\n<w:sectPr><w:pgSz w:w=\"12240\" w:h=\"15840\"/>\
+         <w:pgMar w:top=\"1440\" w:right=\"1440\" w:bottom=\"1440\" w:left=\"1440\" \
+         w:header=\"720\" w:footer=\"720\" w:gutter=\"0\"/></w:sectPr>\n</w:body>\n</w:document>\n",
+        W_NS, body
+    );
+    let comments = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<w:comments {}>\n{}</w:comments>\n",
+        W_NS, comments
+    );
+    (document, comments)
+}
+
+/// Writes `doc` as a Word document, chapters become `Heading 1` and sections are separated by
+/// scene breaks.
+pub fn write_docx<W: Write + Seek>(
+    doc: &Document,
+    synopsis_comments: bool,
+    out: W,
+) -> Result<(), MyError> {
+    let (document, comments) = document_and_comments(doc, synopsis_comments);
+    let mut zip = ZipWriter::new(out);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let parts = [
+        ("[Content_Types].xml", CONTENT_TYPES.to_string()),
+        ("_rels/.rels", RELS.to_string()),
+        ("docProps/core.xml", core(doc)),
+        ("word/_rels/document.xml.rels", DOCUMENT_RELS.to_string()),
+        ("word/document.xml", document),
+        ("word/styles.xml", styles()),
+        ("word/settings.xml", SETTINGS.to_string()),
+        ("word/comments.xml", comments),
+    ];
+    for (name, content) in parts.iter() {
+        zip.start_file(*name, options)?;
+        zip.write_all(content.as_bytes())?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::{BookMeta, Chapter, Section};
+    use crate::bookcompiler::Matter;
+    use std::io::{Cursor, Read};
+
+    fn section(id: &str, content: &str, synopsis: &str) -> Section {
+        Section {
+            id: id.to_string(),
+            name: id.to_string(),
+            content: content.to_string(),
+            synopsis: synopsis.to_string(),
+        }
+    }
+
+    fn doc() -> Document {
+        Document {
+            id: "root".to_string(),
+            meta: BookMeta::new("Tom & Jerry"),
+            authors: vec!["akhil".to_string()],
+            chapters: vec![Chapter {
+                id: "c1".to_string(),
+                title: "Chap1".to_string(),
+                sections: vec![
+                    section("s1", "It was **dark**.", "Opening"),
+                    section("s2", "The end", ""),
+                ],
+            }],
+            chapter_headings: true,
+            ..Document::default()
+        }
+    }
+
+    #[test]
+    fn chapters_are_headings_and_sections_separated_by_scene_breaks() {
+        let (document, comments) = document_and_comments(&doc(), false);
+        assert!(document.contains(
+            "<w:p><w:pPr><w:pStyle w:val=\"Heading1\"/></w:pPr><w:r><w:t xml:space=\"preserve\">Chap1</w:t></w:r></w:p>"
+        ));
+        assert!(document
+            .contains("<w:r><w:rPr><w:b/></w:rPr><w:t xml:space=\"preserve\">dark</w:t></w:r>"));
+        assert!(document.contains(
+            "<w:pStyle w:val=\"SceneBreak\"/></w:pPr><w:r><w:t xml:space=\"preserve\">#</w:t></w:r></w:p><w:p>"
+        ));
+        assert!(!document.contains("commentReference"));
+        assert!(!comments.contains("<w:comment "));
+    }
+
+    #[test]
+    fn run_properties_follow_the_schema_order() {
+        let span = Span {
+            text: "x".to_string(),
+            emphasis: true,
+            strong: true,
+            code: true,
+        };
+        assert_eq!(
+            run(&span),
+            "<w:r><w:rPr><w:rFonts w:ascii=\"Courier New\" w:hAnsi=\"Courier New\"/><w:b/><w:i/>\
+             </w:rPr><w:t xml:space=\"preserve\">x</w:t></w:r>"
+        );
+        //thematic breaks look like the breaks between sections
+        assert_eq!(
+            block(&Block::Rule),
+            paragraph("SceneBreak", &[text(SCENE_BREAK)])
+        );
+    }
+
+    #[test]
+    fn front_matter_comes_before_the_chapters() {
+        let mut doc = doc();
+        doc.meta.about_author = Some("Akhil writes.".to_string());
+        doc.matter = Matter {
+            title_page: true,
+            copyright: true,
+            dedication: true,
+            toc: true,
+            about_author: true,
+        };
+        let (document, _) = document_and_comments(&doc, false);
+        let title = document.find("w:val=\"Title\"").unwrap();
+        let copyright = document.find("Copyright \u{a9}").unwrap();
+        let toc = document.find(" TOC \\o").unwrap();
+        let chapter = document.find(">Chap1<").unwrap();
+        let about = document.find(">About the Author<").unwrap();
+        assert!(title < copyright && copyright < toc && toc < chapter && chapter < about);
+
+        doc.matter = Matter::default();
+        let (document, _) = document_and_comments(&doc, false);
+        assert!(!document.contains("w:val=\"Title\"") && !document.contains(" TOC "));
+    }
+
+    #[test]
+    fn synopses_become_comments() {
+        let (document, comments) = document_and_comments(&doc(), true);
+        assert!(document.contains(
+            "<w:commentRangeStart w:id=\"0\"/><w:r><w:t xml:space=\"preserve\">It was </w:t></w:r>"
+        ));
+        assert!(!document.contains("w:id=\"1\""));
+        assert!(comments.contains("<w:comment w:id=\"0\" w:author=\"akhil\""));
+        assert!(comments.contains("Opening"));
+    }
+
+    #[test]
+    fn docx_contains_all_parts() {
+        let mut buf = Cursor::new(Vec::new());
+        write_docx(&doc(), false, &mut buf).unwrap();
+        let mut zip = zip::ZipArchive::new(buf).unwrap();
+        let mut types = String::new();
+        zip.by_name("[Content_Types].xml")
+            .unwrap()
+            .read_to_string(&mut types)
+            .unwrap();
+        for i in 0..zip.len() {
+            let name = zip.by_index(i).unwrap().name().to_string();
+            if name.ends_with(".xml") && name != "[Content_Types].xml" {
+                assert!(types.contains(&format!("PartName=\"/{}\"", name)));
+            }
+        }
+    }
+}
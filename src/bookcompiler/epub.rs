@@ -0,0 +1,400 @@
+use super::matter;
+use super::render::escape_html;
+use super::Document;
+use crate::error::MyError;
+use chrono::Utc;
+use std::fs;
+use std::io::{Seek, Write};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+const CONTAINER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+fn chapter_file(index: usize) -> String {
+    format!("chapter-{:03}.xhtml", index + 1)
+}
+
+fn xhtml(doc: &Document, title: &str, body: &str) -> String {
+    let style = if doc.theme.is_some() {
+        "<link rel=\"stylesheet\" type=\"text/css\" href=\"theme.css\"/>\n"
+    } else {
+        ""
+    };
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE html>\n\
+         <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" \
+         xml:lang=\"{lang}\" lang=\"{lang}\">\n\
+         <head>\n<meta charset=\"utf-8\"/>\n<title>{title}</title>\n{style}</head>\n\
+         <body>\n{body}</body>\n</html>\n",
+        lang = escape_html(doc.language()),
+        title = escape_html(title),
+        style = style,
+        body = body
+    )
+}
+
+fn media_type(path: &str) -> &'static str {
+    let extension = path.rsplit('.').next().unwrap_or("").to_lowercase();
+    match extension.as_str() {
+        "css" => "text/css",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        _ => "application/octet-stream",
+    }
+}
+
+fn chapter_xhtml(doc: &Document, index: usize) -> String {
+    let chapter = &doc.chapters[index];
+    let mut body = format!(
+        "<section epub:type=\"chapter\" id=\"chapter-{}\">\n",
+        index + 1
+    );
+    if doc.chapter_headings {
+        body.push_str(&format!("<h1>{}</h1>\n", escape_html(&chapter.title)));
+    }
+    for section in &chapter.sections {
+        body.push_str(&format!(
+            "<section id=\"section-{}\">\n",
+            escape_html(&section.id)
+        ));
+        body.push_str(&doc.section_html(section));
+        body.push_str("</section>\n");
+    }
+    body.push_str("</section>\n");
+    xhtml(doc, &chapter.title, &body)
+}
+
+// generated pages before the chapters as (id, body), their file is `<id>.xhtml`
+fn front_matter(doc: &Document) -> Vec<(&'static str, String)> {
+    let mut pages = Vec::new();
+    if doc.matter.title_page {
+        pages.push(("title", matter::title_html(doc)));
+    }
+    if let Some(copyright) = matter::copyright_html(doc) {
+        pages.push(("copyright", copyright));
+    }
+    if let Some(dedication) = matter::dedication_html(doc) {
+        pages.push(("dedication", dedication));
+    }
+    pages
+}
+
+fn back_matter(doc: &Document) -> Vec<(&'static str, String)> {
+    matter::about_author_html(doc)
+        .map(|about| ("about", about))
+        .into_iter()
+        .collect()
+}
+
+// chapters are the top level of the table of contents, their sections are nested below them
+fn nav_xhtml(doc: &Document) -> String {
+    let mut body = String::from("<nav epub:type=\"toc\" id=\"toc\">\n<h1>Contents</h1>\n<ol>\n");
+    for (i, chapter) in doc.chapters.iter().enumerate() {
+        let file = chapter_file(i);
+        body.push_str(&format!(
+            "<li><a href=\"{}\">{}</a>\n<ol>\n",
+            file,
+            escape_html(&chapter.title)
+        ));
+        for section in &chapter.sections {
+            body.push_str(&format!(
+                "<li><a href=\"{}#section-{}\">{}</a></li>\n",
+                file,
+                escape_html(&section.id),
+                escape_html(&section.name)
+            ));
+        }
+        body.push_str("</ol>\n</li>\n");
+    }
+    body.push_str("</ol>\n</nav>\n");
+    xhtml(doc, &doc.meta.title, &body)
+}
+
+fn content_opf(doc: &Document) -> String {
+    let identifier = match doc.meta.isbn {
+        Some(ref isbn) => format!("urn:isbn:{}", isbn),
+        None => format!("urn:collabook:{}", doc.id),
+    };
+
+    let mut metadata = format!(
+        "<dc:identifier id=\"book-id\">{}</dc:identifier>\n\
+         <dc:title>{}</dc:title>\n\
+         <dc:language>{}</dc:language>\n\
+         <meta property=\"dcterms:modified\">{}</meta>\n",
+        escape_html(&identifier),
+        escape_html(&doc.meta.title),
+        escape_html(doc.language()),
+        Utc::now().format("%Y-%m-%dT%H:%M:%SZ")
+    );
+    for author in &doc.authors {
+        metadata.push_str(&format!(
+            "<dc:creator>{}</dc:creator>\n",
+            escape_html(author)
+        ));
+    }
+    let optional = [
+        ("description", &doc.meta.description),
+        ("publisher", &doc.meta.publisher),
+        ("rights", &doc.meta.rights),
+    ];
+    for (element, value) in optional.iter() {
+        if let Some(value) = value {
+            metadata.push_str(&format!(
+                "<dc:{0}>{1}</dc:{0}>\n",
+                element,
+                escape_html(value)
+            ));
+        }
+    }
+
+    let mut manifest = String::from(
+        "<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n",
+    );
+    //pages in reading order as (id, file)
+    let mut pages: Vec<(String, String)> = front_matter(doc)
+        .into_iter()
+        .map(|(id, _)| (id.to_string(), format!("{}.xhtml", id)))
+        .collect();
+    let front = pages.len();
+    for i in 0..doc.chapters.len() {
+        pages.push((format!("chapter-{}", i + 1), chapter_file(i)));
+    }
+    for (id, _) in back_matter(doc) {
+        pages.push((id.to_string(), format!("{}.xhtml", id)));
+    }
+
+    if let Some(ref theme) = doc.theme {
+        manifest.push_str("<item id=\"theme\" href=\"theme.css\" media-type=\"text/css\"/>\n");
+        for (i, (rel_path, _)) in theme.assets.iter().enumerate() {
+            manifest.push_str(&format!(
+                "<item id=\"theme-asset-{}\" href=\"{}\" media-type=\"{}\"/>\n",
+                i + 1,
+                escape_html(rel_path),
+                media_type(rel_path)
+            ));
+        }
+    }
+
+    let mut spine = String::new();
+    for (i, (id, file)) in pages.iter().enumerate() {
+        //the navigation document doubles as the table of contents in the reading order
+        if i == front && doc.matter.toc {
+            spine.push_str("<itemref idref=\"nav\"/>\n");
+        }
+        manifest.push_str(&format!(
+            "<item id=\"{}\" href=\"{}\" media-type=\"application/xhtml+xml\"/>\n",
+            id, file
+        ));
+        spine.push_str(&format!("<itemref idref=\"{}\"/>\n", id));
+    }
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\">\n\
+         <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n{}</metadata>\n\
+         <manifest>\n{}</manifest>\n\
+         <spine>\n{}</spine>\n\
+         </package>\n",
+        metadata, manifest, spine
+    )
+}
+
+/// Writes `doc` as an EPUB 3 container with one spine item per chapter.
+pub fn write_epub<W: Write + Seek>(doc: &Document, out: W) -> Result<(), MyError> {
+    let mut zip = ZipWriter::new(out);
+    let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    //mimetype has to be the first entry and must not be compressed
+    zip.start_file(
+        "mimetype",
+        FileOptions::default().compression_method(CompressionMethod::Stored),
+    )?;
+    zip.write_all(b"application/epub+zip")?;
+
+    zip.start_file("META-INF/container.xml", deflated)?;
+    zip.write_all(CONTAINER.as_bytes())?;
+
+    zip.start_file("OEBPS/content.opf", deflated)?;
+    zip.write_all(content_opf(doc).as_bytes())?;
+
+    zip.start_file("OEBPS/nav.xhtml", deflated)?;
+    zip.write_all(nav_xhtml(doc).as_bytes())?;
+
+    for i in 0..doc.chapters.len() {
+        zip.start_file(format!("OEBPS/{}", chapter_file(i)), deflated)?;
+        zip.write_all(chapter_xhtml(doc, i).as_bytes())?;
+    }
+
+    for (id, body) in front_matter(doc).into_iter().chain(back_matter(doc)) {
+        zip.start_file(format!("OEBPS/{}.xhtml", id), deflated)?;
+        zip.write_all(xhtml(doc, &doc.meta.title, &body).as_bytes())?;
+    }
+
+    if let Some(ref theme) = doc.theme {
+        zip.start_file("OEBPS/theme.css", deflated)?;
+        zip.write_all(theme.css.as_bytes())?;
+        for (rel_path, path) in &theme.assets {
+            zip.start_file(format!("OEBPS/{}", rel_path), deflated)?;
+            zip.write_all(&fs::read(path)?)?;
+        }
+    }
+
+    zip.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::{BookMeta, Chapter, Section};
+    use crate::bookcompiler::{Matter, Theme};
+    use std::io::{Cursor, Read};
+    use tempdir::TempDir;
+
+    fn section(id: &str, name: &str, content: &str) -> Section {
+        Section {
+            id: id.to_string(),
+            name: name.to_string(),
+            content: content.to_string(),
+            synopsis: String::new(),
+        }
+    }
+
+    #[test]
+    fn epub_contains_a_spine_item_per_chapter() {
+        let mut doc = Document {
+            id: "root".to_string(),
+            meta: BookMeta::new("Tom & Jerry"),
+            authors: vec!["akhil".to_string()],
+            chapters: vec![
+                Chapter {
+                    id: "c1".to_string(),
+                    title: "Chap1".to_string(),
+                    sections: vec![section("s1", "Sec1", "one"), section("s2", "Sec2", "two")],
+                },
+                Chapter {
+                    id: "c2".to_string(),
+                    title: "Chap2".to_string(),
+                    sections: vec![section("s3", "Sec1", "three")],
+                },
+            ],
+            chapter_headings: true,
+            ..Document::default()
+        };
+
+        let mut buf = Cursor::new(Vec::new());
+        write_epub(&doc, &mut buf).unwrap();
+
+        let mut zip = zip::ZipArchive::new(buf).unwrap();
+        assert_eq!(zip.by_index(0).unwrap().name(), "mimetype");
+
+        let mut opf = String::new();
+        zip.by_name("OEBPS/content.opf")
+            .unwrap()
+            .read_to_string(&mut opf)
+            .unwrap();
+        assert!(opf.contains("<dc:title>Tom &amp; Jerry</dc:title>"));
+        assert!(opf.contains("<dc:creator>akhil</dc:creator>"));
+        assert!(opf.contains("<itemref idref=\"chapter-1\"/>\n<itemref idref=\"chapter-2\"/>"));
+
+        let mut nav = String::new();
+        zip.by_name("OEBPS/nav.xhtml")
+            .unwrap()
+            .read_to_string(&mut nav)
+            .unwrap();
+        assert!(nav.contains("<a href=\"chapter-001.xhtml#section-s2\">Sec2</a>"));
+
+        let mut chapter = String::new();
+        zip.by_name("OEBPS/chapter-002.xhtml")
+            .unwrap()
+            .read_to_string(&mut chapter)
+            .unwrap();
+        assert!(chapter.contains("<h1>Chap2</h1>"));
+        assert!(chapter.contains("<p>three</p>"));
+
+        doc.matter = Matter {
+            title_page: true,
+            copyright: true,
+            dedication: true,
+            toc: true,
+            about_author: true,
+        };
+        let mut buf = Cursor::new(Vec::new());
+        write_epub(&doc, &mut buf).unwrap();
+        let mut zip = zip::ZipArchive::new(buf).unwrap();
+        let mut opf = String::new();
+        zip.by_name("OEBPS/content.opf")
+            .unwrap()
+            .read_to_string(&mut opf)
+            .unwrap();
+        assert!(opf.contains(
+            "<spine>\n<itemref idref=\"title\"/>\n<itemref idref=\"copyright\"/>\n\
+             <itemref idref=\"nav\"/>\n<itemref idref=\"chapter-1\"/>\n<itemref idref=\"chapter-2\"/>\n</spine>"
+        ));
+        assert!(opf.contains("<item id=\"title\" href=\"title.xhtml\""));
+        assert!(zip.by_name("OEBPS/title.xhtml").is_ok());
+    }
+
+    #[test]
+    fn theme_is_packaged_and_linked_from_every_page() {
+        let temp_dir = TempDir::new("test_epub").unwrap();
+        let font = temp_dir.path().join("a.ttf");
+        fs::write(&font, "font").unwrap();
+        let doc = Document {
+            id: "root".to_string(),
+            meta: BookMeta::new("Book"),
+            chapters: vec![Chapter {
+                id: "c1".to_string(),
+                title: "Chap1".to_string(),
+                sections: vec![section("s1", "Sec1", "one")],
+            }],
+            theme: Some(Theme {
+                name: "mine".to_string(),
+                css: "body { color: red; }".to_string(),
+                assets: vec![("fonts/a.ttf".to_string(), font)],
+                dir: Some(temp_dir.path().to_path_buf()),
+                typesetting: Default::default(),
+            }),
+            matter: Matter {
+                title_page: true,
+                ..Matter::default()
+            },
+            ..Document::default()
+        };
+
+        let mut buf = Cursor::new(Vec::new());
+        write_epub(&doc, &mut buf).unwrap();
+        let mut zip = zip::ZipArchive::new(buf).unwrap();
+        let mut read = |name: &str| {
+            let mut out = String::new();
+            zip.by_name(name).unwrap().read_to_string(&mut out).unwrap();
+            out
+        };
+        let opf = read("OEBPS/content.opf");
+        assert!(opf.contains("<item id=\"theme\" href=\"theme.css\" media-type=\"text/css\"/>"));
+        assert!(opf.contains("href=\"fonts/a.ttf\" media-type=\"font/ttf\""));
+        assert_eq!(read("OEBPS/theme.css"), "body { color: red; }");
+        assert_eq!(read("OEBPS/fonts/a.ttf"), "font");
+        for page in &[
+            "OEBPS/chapter-001.xhtml",
+            "OEBPS/nav.xhtml",
+            "OEBPS/title.xhtml",
+        ] {
+            assert!(read(page)
+                .contains("<link rel=\"stylesheet\" type=\"text/css\" href=\"theme.css\"/>"));
+        }
+    }
+}
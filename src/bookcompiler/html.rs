@@ -0,0 +1,248 @@
+use super::matter::{self, ABOUT_AUTHOR_TITLE};
+use super::render::escape_html;
+use super::Document;
+use crate::error::MyError;
+use std::fs;
+use std::path::Path;
+
+const STYLE: &str = "body { max-width: 40em; margin: 0 auto; padding: 1em; \
+                     font-family: Georgia, serif; line-height: 1.5; }\n\
+                     nav.pages { display: flex; justify-content: space-between; margin: 2em 0; }\n\
+                     .title-page, .dedication { text-align: center; margin: 4em 0; }\n";
+
+// the css of the theme follows the default style so it can override it
+fn page(doc: &Document, title: &str, body: &str) -> String {
+    let theme = doc
+        .theme
+        .as_ref()
+        .map(|theme| theme.css.as_str())
+        .unwrap_or("");
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"{}\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{}</title>\n<style>\n{}{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape_html(doc.language()),
+        escape_html(title),
+        STYLE,
+        theme,
+        body
+    )
+}
+
+const ABOUT_PAGE: &str = "about.html";
+
+fn chapter_page(index: usize) -> String {
+    format!("chapter-{:03}.html", index + 1)
+}
+
+fn chapter_body(doc: &Document, index: usize) -> String {
+    let chapter = &doc.chapters[index];
+    let mut body = format!("<section class=\"chapter\" id=\"chapter-{}\">\n", index + 1);
+    if doc.chapter_headings {
+        body.push_str(&format!("<h1>{}</h1>\n", escape_html(&chapter.title)));
+    }
+    for section in &chapter.sections {
+        body.push_str(&format!(
+            "<section class=\"section\" id=\"section-{}\">\n",
+            escape_html(&section.id)
+        ));
+        body.push_str(&doc.section_html(section));
+        body.push_str("</section>\n");
+    }
+    body.push_str("</section>\n");
+    body
+}
+
+// the title and contents always open the book, it is how readers get to the chapters
+fn front_matter(doc: &Document, href: impl Fn(usize) -> String) -> String {
+    let mut out = matter::title_html(doc);
+    out.push_str(&matter::copyright_html(doc).unwrap_or_default());
+    out.push_str(&matter::dedication_html(doc).unwrap_or_default());
+    out.push_str(&matter::contents_html(doc, href));
+    out
+}
+
+/// The whole book as one html file with the styles inlined.
+pub fn single_page(doc: &Document) -> String {
+    let mut body = front_matter(doc, |i| format!("#chapter-{}", i + 1));
+    for i in 0..doc.chapters.len() {
+        body.push_str(&chapter_body(doc, i));
+    }
+    body.push_str(&matter::about_author_html(doc).unwrap_or_default());
+    page(doc, &doc.meta.title, &body)
+}
+
+/// An index page plus one page per chapter, as (file name, html) pairs.
+pub fn site_pages(doc: &Document) -> Vec<(String, String)> {
+    let index = front_matter(doc, chapter_page);
+    let about = matter::about_author_html(doc);
+    let mut pages = vec![("index.html".to_string(), page(doc, &doc.meta.title, &index))];
+
+    for (i, chapter) in doc.chapters.iter().enumerate() {
+        let mut body = chapter_body(doc, i);
+        body.push_str("<nav class=\"pages\">\n");
+        if i > 0 {
+            body.push_str(&format!(
+                "<a rel=\"prev\" href=\"{}\">Previous</a>\n",
+                chapter_page(i - 1)
+            ));
+        }
+        body.push_str("<a href=\"index.html\">Contents</a>\n");
+        if i + 1 < doc.chapters.len() {
+            body.push_str(&format!(
+                "<a rel=\"next\" href=\"{}\">Next</a>\n",
+                chapter_page(i + 1)
+            ));
+        } else if about.is_some() {
+            body.push_str(&format!(
+                "<a rel=\"next\" href=\"{}\">Next</a>\n",
+                ABOUT_PAGE
+            ));
+        }
+        body.push_str("</nav>\n");
+
+        let title = format!("{} - {}", chapter.title, doc.meta.title);
+        pages.push((chapter_page(i), page(doc, &title, &body)));
+    }
+
+    if let Some(mut body) = about {
+        body.push_str("<nav class=\"pages\">\n<a href=\"index.html\">Contents</a>\n</nav>\n");
+        let title = format!("{} - {}", ABOUT_AUTHOR_TITLE, doc.meta.title);
+        pages.push((ABOUT_PAGE.to_string(), page(doc, &title, &body)));
+    }
+    pages
+}
+
+// the directory is recreated so pages of removed chapters don't linger
+fn recreate(dir: &Path) -> Result<(), MyError> {
+    if dir.exists() {
+        fs::remove_dir_all(dir)?;
+    }
+    fs::create_dir_all(dir)?;
+    Ok(())
+}
+
+// fonts of the theme are referred to relative to the pages
+fn copy_theme_assets(doc: &Document, dir: &Path) -> Result<(), MyError> {
+    match doc.theme {
+        Some(ref theme) => theme.copy_assets(dir),
+        None => Ok(()),
+    }
+}
+
+pub fn write_single(doc: &Document, dir: &Path) -> Result<(), MyError> {
+    recreate(dir)?;
+    fs::write(dir.join("index.html"), single_page(doc))?;
+    copy_theme_assets(doc, dir)
+}
+
+pub fn write_site(doc: &Document, dir: &Path) -> Result<(), MyError> {
+    recreate(dir)?;
+    for (name, html) in site_pages(doc) {
+        fs::write(dir.join(name), html)?;
+    }
+    copy_theme_assets(doc, dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::{BookMeta, Chapter, Section};
+    use crate::bookcompiler::Theme;
+    use tempdir::TempDir;
+
+    fn chapter(id: &str, title: &str, content: &str) -> Chapter {
+        Chapter {
+            id: id.to_string(),
+            title: title.to_string(),
+            sections: vec![Section {
+                id: format!("{}-1", id),
+                name: "Sec1".to_string(),
+                content: content.to_string(),
+                synopsis: String::new(),
+            }],
+        }
+    }
+
+    fn doc() -> Document {
+        Document {
+            id: "root".to_string(),
+            meta: BookMeta::new("Tom & Jerry"),
+            authors: vec!["akhil".to_string()],
+            chapters: vec![chapter("c1", "Chap1", "one"), chapter("c2", "Chap2", "two")],
+            chapter_headings: true,
+            ..Document::default()
+        }
+    }
+
+    #[test]
+    fn single_page_links_to_chapter_anchors() {
+        let html = single_page(&doc());
+        assert!(html.contains("<title>Tom &amp; Jerry</title>"));
+        assert!(html.contains("<a href=\"#chapter-2\">Chap2</a>"));
+        assert!(html.contains("<section class=\"chapter\" id=\"chapter-2\">\n<h1>Chap2</h1>"));
+        assert!(html.contains("<p>two</p>"));
+    }
+
+    #[test]
+    fn site_has_index_and_a_page_per_chapter() {
+        let pages = site_pages(&doc());
+        let names: Vec<&str> = pages.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["index.html", "chapter-001.html", "chapter-002.html"]
+        );
+        assert!(pages[0]
+            .1
+            .contains("<a href=\"chapter-002.html\">Chap2</a>"));
+        assert!(pages[1]
+            .1
+            .contains("<a rel=\"next\" href=\"chapter-002.html\">"));
+        assert!(!pages[1].1.contains("rel=\"prev\""));
+        assert!(pages[2]
+            .1
+            .contains("<a rel=\"prev\" href=\"chapter-001.html\">"));
+    }
+
+    #[test]
+    fn about_the_author_follows_the_last_chapter() {
+        let mut doc = doc();
+        doc.meta.about_author = Some("Akhil writes.".to_string());
+        doc.matter.about_author = true;
+        let html = single_page(&doc);
+        assert!(html.find("<p>two</p>").unwrap() < html.find("About the Author").unwrap());
+
+        let pages = site_pages(&doc);
+        assert_eq!(pages.last().unwrap().0, "about.html");
+        assert!(pages[2].1.contains("<a rel=\"next\" href=\"about.html\">"));
+
+        doc.matter.about_author = false;
+        assert!(single_page(&doc).contains("class=\"toc\""));
+        let pages = site_pages(&doc);
+        assert_eq!(pages.len(), 3);
+        assert!(pages[0]
+            .1
+            .contains("<a href=\"chapter-002.html\">Chap2</a>"));
+    }
+
+    #[test]
+    fn theme_css_follows_the_default_style_and_its_fonts_are_copied() {
+        let temp_dir = TempDir::new("test_html").unwrap();
+        let font = temp_dir.path().join("a.ttf");
+        fs::write(&font, "font").unwrap();
+        let mut doc = doc();
+        doc.theme = Some(Theme {
+            name: "mine".to_string(),
+            css: "body { color: red; }\n".to_string(),
+            assets: vec![("fonts/a.ttf".to_string(), font)],
+            dir: Some(temp_dir.path().to_path_buf()),
+            typesetting: Default::default(),
+        });
+
+        let dir = temp_dir.path().join("html");
+        write_single(&doc, &dir).unwrap();
+        let html = fs::read_to_string(dir.join("index.html")).unwrap();
+        assert!(html.contains("margin: 4em 0; }\nbody { color: red; }\n</style>"));
+        assert!(dir.join("fonts/a.ttf").is_file());
+    }
+}
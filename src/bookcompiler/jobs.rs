@@ -0,0 +1,246 @@
+use super::builds::download;
+use super::{AppState, OutputFormat};
+use crate::book::CompileBookRequest;
+use crate::error::MyError;
+use actix::Message;
+use actix_web::{fs::NamedFile, HttpRequest, HttpResponse, Json, Responder, State};
+use chrono::Utc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Finished jobs beyond this many are forgotten, oldest first.
+const MAX_JOBS: usize = 50;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Job {
+    pub id: String,
+    pub location: PathBuf,
+    pub format: OutputFormat,
+    pub status: JobStatus,
+    /// Between 0 and 1
+    pub progress: f32,
+    /// What the job is doing, or why it failed
+    pub message: String,
+    /// The compiled file, or directory for multi file formats
+    pub artifact: Option<PathBuf>,
+    pub created: String,
+    pub finished: Option<String>,
+}
+
+impl Job {
+    fn is_finished(&self) -> bool {
+        match self.status {
+            JobStatus::Queued | JobStatus::Running => false,
+            _ => true,
+        }
+    }
+}
+
+/// Compile jobs shared between the http handlers and the compiler threads.
+#[derive(Clone, Default)]
+pub struct Jobs(Arc<Mutex<Vec<Job>>>);
+
+impl Jobs {
+    fn lock(&self) -> MutexGuard<Vec<Job>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn update<F: FnOnce(&mut Job)>(&self, id: &str, f: F) {
+        if let Some(job) = self.lock().iter_mut().find(|job| job.id == id) {
+            f(job);
+        }
+    }
+
+    pub fn queue(&self, location: &Path, format: OutputFormat) -> Job {
+        let job = Job {
+            id: uuid::Uuid::new_v4().to_string(),
+            location: location.to_path_buf(),
+            format,
+            status: JobStatus::Queued,
+            progress: 0.0,
+            message: String::new(),
+            artifact: None,
+            created: Utc::now().to_rfc3339(),
+            finished: None,
+        };
+
+        let mut jobs = self.lock();
+        jobs.push(job.clone());
+        while jobs.len() > MAX_JOBS {
+            match jobs.iter().position(Job::is_finished) {
+                Some(i) => jobs.remove(i),
+                None => break,
+            };
+        }
+        job
+    }
+
+    pub fn get(&self, id: &str) -> Result<Job, MyError> {
+        self.lock()
+            .iter()
+            .find(|job| job.id == id)
+            .cloned()
+            .ok_or_else(|| MyError(format!("Compile job {} not found", id)))
+    }
+
+    /// Newest first.
+    pub fn recent(&self) -> Vec<Job> {
+        self.lock().iter().rev().cloned().collect()
+    }
+
+    /// Reports progress of a running job, fails if the job was cancelled so the compiler can
+    /// stop at the next step.
+    pub fn progress(&self, id: &str, progress: f32, message: &str) -> Result<(), MyError> {
+        if self.is_cancelled(id) {
+            Err("Compile was cancelled")?
+        }
+        self.update(id, |job| {
+            job.status = JobStatus::Running;
+            job.progress = progress;
+            job.message = message.to_string();
+        });
+        Ok(())
+    }
+
+    pub fn is_cancelled(&self, id: &str) -> bool {
+        self.get(id)
+            .map(|job| job.status == JobStatus::Cancelled)
+            .unwrap_or(false)
+    }
+
+    pub fn finish(&self, id: &str, result: Result<PathBuf, MyError>) {
+        self.update(id, |job| {
+            if job.status == JobStatus::Cancelled {
+                return;
+            }
+            match result {
+                Ok(artifact) => {
+                    job.status = JobStatus::Done;
+                    job.progress = 1.0;
+                    job.message = String::new();
+                    job.artifact = Some(artifact);
+                }
+                Err(e) => {
+                    job.status = JobStatus::Failed;
+                    job.message = e.to_string();
+                }
+            }
+            job.finished = Some(Utc::now().to_rfc3339());
+        });
+    }
+
+    /// Queued jobs are never started, running ones stop before their next step and a running
+    /// pdf renderer is killed.
+    pub fn cancel(&self, id: &str) -> Result<Job, MyError> {
+        let job = self.get(id)?;
+        if job.is_finished() {
+            Err("Compile job has already finished")?
+        }
+        self.update(id, |job| {
+            job.status = JobStatus::Cancelled;
+            job.finished = Some(Utc::now().to_rfc3339());
+        });
+        self.get(id)
+    }
+}
+
+/// Compiles `request` on the compiler threads and records the outcome in the job `id`.
+pub struct CompileJob {
+    pub id: String,
+    pub request: CompileBookRequest,
+}
+
+impl Message for CompileJob {
+    type Result = ();
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CompileJobRequest {
+    id: String,
+}
+
+pub fn get_compile_job(
+    (state, info): (State<AppState>, Json<CompileJobRequest>),
+) -> Result<impl Responder, MyError> {
+    Ok(HttpResponse::Ok().json(state.jobs.get(&info.id)?))
+}
+
+pub fn get_compile_jobs(req: &HttpRequest<AppState>) -> Result<impl Responder, MyError> {
+    Ok(HttpResponse::Ok().json(req.state().jobs.recent()))
+}
+
+pub fn cancel_compile_job(
+    (state, info): (State<AppState>, Json<CompileJobRequest>),
+) -> Result<impl Responder, MyError> {
+    Ok(HttpResponse::Ok().json(state.jobs.cancel(&info.id)?))
+}
+
+pub fn get_compile_artifact(
+    (state, info): (State<AppState>, Json<CompileJobRequest>),
+) -> Result<NamedFile, MyError> {
+    let job = state.jobs.get(&info.id)?;
+    download(&job.artifact.ok_or("Compile job has no artifact")?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn job_lifecycle() {
+        let jobs = Jobs::default();
+        let job = jobs.queue(Path::new("book"), OutputFormat::Epub);
+        assert_eq!(job.status, JobStatus::Queued);
+
+        jobs.progress(&job.id, 0.5, "Rendering").unwrap();
+        let running = jobs.get(&job.id).unwrap();
+        assert_eq!(running.status, JobStatus::Running);
+        assert_eq!(running.message, "Rendering");
+
+        jobs.finish(&job.id, Ok(PathBuf::from("book/target/book.epub")));
+        let done = jobs.get(&job.id).unwrap();
+        assert_eq!(done.status, JobStatus::Done);
+        assert_eq!(done.artifact, Some(PathBuf::from("book/target/book.epub")));
+        assert!(jobs.cancel(&job.id).is_err());
+
+        let failed = jobs.queue(Path::new("book"), OutputFormat::Pdf);
+        jobs.finish(&failed.id, Err(MyError("File doesn't exist".to_string())));
+        assert_eq!(jobs.get(&failed.id).unwrap().status, JobStatus::Failed);
+        assert_eq!(jobs.recent()[0].id, failed.id);
+    }
+
+    #[test]
+    fn cancelled_job_stops_at_next_step() {
+        let jobs = Jobs::default();
+        let job = jobs.queue(Path::new("book"), OutputFormat::Pdf);
+        jobs.progress(&job.id, 0.1, "Opening book").unwrap();
+        jobs.cancel(&job.id).unwrap();
+
+        assert!(jobs.progress(&job.id, 0.5, "Rendering").is_err());
+        jobs.finish(&job.id, Err(MyError("Compile was cancelled".to_string())));
+        assert_eq!(jobs.get(&job.id).unwrap().status, JobStatus::Cancelled);
+    }
+
+    #[test]
+    fn old_finished_jobs_are_dropped() {
+        let jobs = Jobs::default();
+        let first = jobs.queue(Path::new("book"), OutputFormat::Pdf);
+        for _ in 0..MAX_JOBS {
+            let job = jobs.queue(Path::new("book"), OutputFormat::Pdf);
+            jobs.finish(&job.id, Ok(PathBuf::from("book.pdf")));
+        }
+        let recent = jobs.recent();
+        assert_eq!(recent.len(), MAX_JOBS);
+        //unfinished jobs are kept
+        assert!(recent.iter().any(|job| job.id == first.id));
+    }
+}
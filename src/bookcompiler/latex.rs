@@ -0,0 +1,332 @@
+use super::blocks::{section_blocks, Block, Span};
+use super::matter::{self, ABOUT_AUTHOR_TITLE};
+use super::Document;
+use crate::error::MyError;
+use std::fs;
+use std::path::Path;
+
+const DEFAULT_PREAMBLE: &str = "\\usepackage[utf8]{inputenc}\n\\usepackage[T1]{fontenc}\n";
+
+/// Sectioning command used for chapter folders.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum LatexLevel {
+    /// `\chapter`, for book and report like classes
+    Chapter,
+    /// `\section`, for article like classes which have no chapters
+    Section,
+}
+
+impl Default for LatexLevel {
+    fn default() -> Self {
+        LatexLevel::Chapter
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LatexOptions {
+    #[serde(default = "default_class")]
+    pub document_class: String,
+    #[serde(default)]
+    pub class_options: Vec<String>,
+    /// Replaces the default preamble, which only sets up utf-8 input
+    #[serde(default)]
+    pub preamble: Option<String>,
+    #[serde(default)]
+    pub top_level: LatexLevel,
+}
+
+fn default_class() -> String {
+    "book".to_string()
+}
+
+impl Default for LatexOptions {
+    fn default() -> Self {
+        LatexOptions {
+            document_class: default_class(),
+            class_options: Vec::new(),
+            preamble: None,
+            top_level: LatexLevel::default(),
+        }
+    }
+}
+
+pub fn escape_latex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '{' | '}' | '$' | '&' | '#' | '_' | '%' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '^' => escaped.push_str("\\textasciicircum{}"),
+            '~' => escaped.push_str("\\textasciitilde{}"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn spans(spans: &[Span]) -> String {
+    let mut out = String::new();
+    for span in spans {
+        let mut text = escape_latex(&span.text).replace('\n', "\\\\\n");
+        if span.code {
+            text = format!("\\texttt{{{}}}", text);
+        }
+        if span.emphasis {
+            text = format!("\\emph{{{}}}", text);
+        }
+        if span.strong {
+            text = format!("\\textbf{{{}}}", text);
+        }
+        out.push_str(&text);
+    }
+    out
+}
+
+fn heading(level: usize, text: &str) -> String {
+    const COMMANDS: &[&str] = &[
+        "chapter",
+        "section",
+        "subsection",
+        "subsubsection",
+        "paragraph",
+        "subparagraph",
+    ];
+    format!(
+        "\\{}{{{}}}\n",
+        COMMANDS[level.min(COMMANDS.len() - 1)],
+        text
+    )
+}
+
+// consecutive items are collected into one list environment
+fn section_tex(blocks: &[Block], top_level: LatexLevel) -> String {
+    let mut out = String::new();
+    let mut list: Option<&str> = None;
+    for block in blocks {
+        let env = match block {
+            Block::Item(Some(_), _) => Some("enumerate"),
+            Block::Item(None, _) => Some("itemize"),
+            _ => None,
+        };
+        if list != env {
+            if let Some(env) = list {
+                out.push_str(&format!("\\end{{{}}}\n\n", env));
+            }
+            if let Some(env) = env {
+                out.push_str(&format!("\\begin{{{}}}\n", env));
+            }
+            list = env;
+        }
+
+        match block {
+            Block::Heading(level, text) => {
+                //headings in a section sit below the chapter heading
+                out.push_str(&heading(top_level as usize + *level as usize, &spans(text)));
+                out.push('\n');
+            }
+            Block::Paragraph(text) => out.push_str(&format!("{}\n\n", spans(text))),
+            Block::Quote(text) => out.push_str(&format!(
+                "\\begin{{quote}}\n{}\n\\end{{quote}}\n\n",
+                spans(text)
+            )),
+            Block::Item(_, text) => out.push_str(&format!("\\item {}\n", spans(text))),
+            Block::Code(code) => out.push_str(&format!(
+                "\\begin{{verbatim}}\n{}\\end{{verbatim}}\n\n",
+                code
+            )),
+            Block::Rule => out.push_str("\\scenebreak\n\n"),
+            //images aren't copied into the project, their description stands in for them
+            Block::Image(_, alt) => out.push_str(&format!("\\emph{{{}}}\n\n", escape_latex(alt))),
+        }
+    }
+    if let Some(env) = list {
+        out.push_str(&format!("\\end{{{}}}\n\n", env));
+    }
+    out
+}
+
+fn chapter_file(index: usize) -> String {
+    format!("chapter-{:03}", index + 1)
+}
+
+fn chapter_tex(doc: &Document, index: usize, options: &LatexOptions) -> String {
+    let chapter = &doc.chapters[index];
+    //without headings chapters still start on a new page
+    let mut out = if doc.chapter_headings {
+        heading(options.top_level as usize, &escape_latex(&chapter.title))
+    } else if options.top_level == LatexLevel::Chapter {
+        "\\clearpage\n".to_string()
+    } else {
+        String::new()
+    };
+    out.push('\n');
+    for (i, section) in chapter.sections.iter().enumerate() {
+        if i > 0 {
+            out.push_str("\\scenebreak\n\n");
+        }
+        let blocks = doc.section_blocks(section);
+        out.push_str(&section_tex(&blocks, options.top_level));
+    }
+    out
+}
+
+// the title and contents always open the project, copyright and dedication get pages without
+// page numbers of their own
+fn front_matter(doc: &Document, options: &LatexOptions) -> String {
+    let mut out = String::from("\\maketitle\n");
+    if let Some(lines) = matter::copyright(doc) {
+        let lines: Vec<String> = lines.iter().map(|line| escape_latex(line)).collect();
+        out.push_str(&format!(
+            "\\clearpage\n\\thispagestyle{{empty}}\n\\vspace*{{\\fill}}\n\\noindent {}\n\\clearpage\n",
+            lines.join("\\\\\n")
+        ));
+    }
+    if let Some(dedication) = matter::dedication(doc) {
+        out.push_str(&format!(
+            "\\thispagestyle{{empty}}\n\\vspace*{{0.25\\textheight}}\n\\begin{{center}}\n{}\\end{{center}}\n\\clearpage\n",
+            section_tex(&section_blocks(dedication, doc.markup, doc.typography), options.top_level)
+        ));
+    }
+    out.push_str("\\tableofcontents\n\n");
+    out
+}
+
+fn main_tex(doc: &Document, options: &LatexOptions) -> String {
+    let class_options = if options.class_options.is_empty() {
+        String::new()
+    } else {
+        format!("[{}]", options.class_options.join(","))
+    };
+    let authors: Vec<String> = doc.authors.iter().map(|a| escape_latex(a)).collect();
+
+    let mut out = format!(
+        "\\documentclass{}{{{}}}\n{}\n\
+         \\providecommand{{\\scenebreak}}{{\\par\\begin{{center}}\\#\\end{{center}}\\par}}\n\n\
+         \\title{{{}}}\n\\author{{{}}}\n\\date{{}}\n\n\
+         \\begin{{document}}\n",
+        class_options,
+        options.document_class,
+        options
+            .preamble
+            .as_ref()
+            .map(String::as_str)
+            .unwrap_or(DEFAULT_PREAMBLE),
+        escape_latex(&doc.meta.title),
+        authors.join(" \\and ")
+    );
+    out.push_str(&front_matter(doc, options));
+    for i in 0..doc.chapters.len() {
+        out.push_str(&format!("\\input{{chapters/{}}}\n", chapter_file(i)));
+    }
+    if let Some(about) = matter::about_author(doc) {
+        let level = options.top_level as usize;
+        //the starred command keeps it unnumbered
+        out.push_str(&format!(
+            "\n{}\n{}",
+            heading(level, ABOUT_AUTHOR_TITLE).replacen("{", "*{", 1),
+            section_tex(
+                &section_blocks(about, doc.markup, doc.typography),
+                options.top_level
+            )
+        ));
+    }
+    out.push_str("\n\\end{document}\n");
+    out
+}
+
+/// Writes `main.tex` and one file per chapter in `chapters/` inside `dir`. Everything else in
+/// `dir`, like the output of a previous latex run, is kept.
+pub fn write_latex(doc: &Document, options: &LatexOptions, dir: &Path) -> Result<(), MyError> {
+    let chapters = dir.join("chapters");
+    if chapters.exists() {
+        fs::remove_dir_all(&chapters)?;
+    }
+    fs::create_dir_all(&chapters)?;
+
+    fs::write(dir.join("main.tex"), main_tex(doc, options))?;
+    for i in 0..doc.chapters.len() {
+        fs::write(
+            chapters.join(format!("{}.tex", chapter_file(i))),
+            chapter_tex(doc, i, options),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::{BookMeta, Chapter, Section};
+    use tempdir::TempDir;
+
+    fn doc() -> Document {
+        let section = |id: &str, content: &str| Section {
+            id: id.to_string(),
+            name: id.to_string(),
+            content: content.to_string(),
+            synopsis: String::new(),
+        };
+        Document {
+            id: "root".to_string(),
+            meta: BookMeta::new("Cost & Benefit"),
+            authors: vec!["akhil".to_string(), "jane".to_string()],
+            chapters: vec![Chapter {
+                id: "c1".to_string(),
+                title: "Intro_1".to_string(),
+                sections: vec![
+                    section("s1", "## Method\n\nWe use *50%*.\n\n- a\n- b"),
+                    section("s2", "Done"),
+                ],
+            }],
+            chapter_headings: true,
+            ..Document::default()
+        }
+    }
+
+    #[test]
+    fn special_characters_are_escaped() {
+        assert_eq!(
+            escape_latex("50% of $x_1 & {y} \\ ~"),
+            "50\\% of \\$x\\_1 \\& \\{y\\} \\textbackslash{} \\textasciitilde{}"
+        );
+    }
+
+    #[test]
+    fn latex_project_has_main_file_and_chapter_inputs() {
+        let temp_dir = TempDir::new("test_latex").unwrap();
+        let options = LatexOptions {
+            document_class: "article".to_string(),
+            class_options: vec!["a4paper".to_string(), "12pt".to_string()],
+            preamble: Some("\\usepackage{amsmath}\n".to_string()),
+            top_level: LatexLevel::Section,
+        };
+        write_latex(&doc(), &options, temp_dir.path()).unwrap();
+
+        let main = fs::read_to_string(temp_dir.path().join("main.tex")).unwrap();
+        assert!(main.starts_with("\\documentclass[a4paper,12pt]{article}\n\\usepackage{amsmath}\n"));
+        assert!(main.contains("\\title{Cost \\& Benefit}\n\\author{akhil \\and jane}"));
+        assert!(main.contains("\\input{chapters/chapter-001}\n"));
+        assert!(main.contains("\\begin{document}\n\\maketitle\n\\tableofcontents\n\n\\input"));
+
+        let mut about = doc();
+        about.meta.about_author = Some("Akhil writes.".to_string());
+        about.matter.copyright = true;
+        about.matter.about_author = true;
+        let main = main_tex(&about, &options);
+        assert!(
+            main.contains("\\begin{document}\n\\maketitle\n\\clearpage\n\\thispagestyle{empty}")
+        );
+        assert!(main.contains("\\section*{About the Author}\n\nAkhil writes.\n\n"));
+
+        let chapter = fs::read_to_string(temp_dir.path().join("chapters/chapter-001.tex")).unwrap();
+        assert_eq!(
+            chapter,
+            "\\section{Intro\\_1}\n\n\\subsubsection{Method}\n\n\
+             We use \\emph{50\\%}.\n\n\\begin{itemize}\n\\item a\n\\item b\n\\end{itemize}\n\n\
+             \\scenebreak\n\nDone\n\n"
+        );
+    }
+}
@@ -0,0 +1,427 @@
+use chrono::Utc;
+#[cfg(feature = "wkhtmltopdf")]
+use wkhtmltopdf::{Margin, PdfBuilder, Size};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum PageSize {
+    A4,
+    A5,
+    B5,
+    Letter,
+    Legal,
+    Executive,
+}
+
+impl Default for PageSize {
+    fn default() -> Self {
+        PageSize::A4
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+}
+
+impl Default for Orientation {
+    fn default() -> Self {
+        Orientation::Portrait
+    }
+}
+
+/// Custom page size in millimeters, a trade paperback is 152 x 229.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct TrimSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Page margins in millimeters.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Margins {
+    pub top: u32,
+    pub bottom: u32,
+    pub left: u32,
+    pub right: u32,
+}
+
+impl Default for Margins {
+    fn default() -> Self {
+        Margins {
+            top: 20,
+            bottom: 20,
+            left: 20,
+            right: 20,
+        }
+    }
+}
+
+/// Text printed diagonally across every page, like DRAFT or the name of whoever a copy is for.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Watermark {
+    pub text: String,
+    /// From 0 for invisible to 1 for solid black
+    #[serde(default = "Watermark::default_opacity")]
+    pub opacity: f64,
+}
+
+impl Watermark {
+    fn default_opacity() -> f64 {
+        0.15
+    }
+}
+
+/// Page setup of a pdf compile.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Layout {
+    pub orientation: Orientation,
+    /// Extra inner margin in millimeters for binding. Pages aren't mirrored, so it is added to
+    /// the left margin.
+    pub gutter: u32,
+    /// Title of the pdf document, the book title when missing
+    pub title: Option<String>,
+    pub dpi: Option<u32>,
+    pub grayscale: bool,
+    pub page_size: PageSize,
+    /// Running header of every page but the first, see `PLACEHOLDERS`. Empty for none.
+    pub header: String,
+    /// Running footer of every page but the first, the page number by default
+    pub footer: String,
+    // fields that are tables go last, toml can't write values after a table
+    pub margins: Margins,
+    /// Overrides `page_size`
+    pub trim_size: Option<TrimSize>,
+    pub watermark: Option<Watermark>,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout {
+            orientation: Orientation::default(),
+            gutter: 0,
+            title: None,
+            dpi: None,
+            grayscale: false,
+            page_size: PageSize::default(),
+            header: String::new(),
+            footer: "{page}".to_string(),
+            margins: Margins::default(),
+            trim_size: None,
+            watermark: None,
+        }
+    }
+}
+
+/// Placeholders of header and footer templates, written in braces like `{page}`. `{chapter}`
+/// is the title of the chapter on the page, `{pages}` the number of pages.
+pub const PLACEHOLDERS: [&str; 6] = ["title", "author", "chapter", "page", "pages", "date"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TemplatePart<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Splits a header or footer template into text and placeholders, braces around anything
+/// else are text.
+pub fn template_parts(template: &str) -> Vec<TemplatePart> {
+    let mut parts = Vec::new();
+    let mut text_start = 0;
+    let mut rest = 0;
+    while let Some(open) = template[rest..].find('{').map(|i| rest + i) {
+        let name = template[open + 1..]
+            .find('}')
+            .map(|end| &template[open + 1..open + 1 + end])
+            .filter(|name| PLACEHOLDERS.contains(name));
+        match name {
+            Some(name) => {
+                if open > text_start {
+                    parts.push(TemplatePart::Text(&template[text_start..open]));
+                }
+                parts.push(TemplatePart::Placeholder(name));
+                text_start = open + name.len() + 2;
+                rest = text_start;
+            }
+            None => rest = open + 1,
+        }
+    }
+    if text_start < template.len() {
+        parts.push(TemplatePart::Text(&template[text_start..]));
+    }
+    parts
+}
+
+/// `template` with every placeholder replaced by `value(placeholder)`.
+pub fn fill_template<F: Fn(&str) -> String>(template: &str, value: F) -> String {
+    template_parts(template)
+        .into_iter()
+        .map(|part| match part {
+            TemplatePart::Text(text) => text.to_string(),
+            TemplatePart::Placeholder(name) => value(name),
+        })
+        .collect()
+}
+
+/// The date `{date}` stands for.
+pub fn today() -> String {
+    Utc::now().format("%Y-%m-%d").to_string()
+}
+
+fn css_string(text: &str) -> String {
+    let escaped = text
+        .replace("\\", "\\\\")
+        .replace("\"", "\\\"")
+        .replace("\n", "\\A ");
+    format!("\"{}\"", escaped)
+}
+
+impl Layout {
+    /// Width and height of the page in millimeters.
+    pub fn page_mm(&self) -> (f64, f64) {
+        let (width, height) = match (self.trim_size, self.page_size) {
+            (Some(TrimSize { width, height }), _) => (f64::from(width), f64::from(height)),
+            (None, PageSize::A4) => (210.0, 297.0),
+            (None, PageSize::A5) => (148.0, 210.0),
+            (None, PageSize::B5) => (176.0, 250.0),
+            (None, PageSize::Letter) => (215.9, 279.4),
+            (None, PageSize::Legal) => (215.9, 355.6),
+            (None, PageSize::Executive) => (184.15, 266.7),
+        };
+        match self.orientation {
+            Orientation::Portrait => (width, height),
+            Orientation::Landscape => (height, width),
+        }
+    }
+
+    /// The page setup as a css `@page` rule, for renderers that take it from the html.
+    pub fn page_css(&self) -> String {
+        let size = match (self.trim_size, self.page_size) {
+            (Some(TrimSize { width, height }), _) => match self.orientation {
+                Orientation::Portrait => format!("{}mm {}mm", width, height),
+                Orientation::Landscape => format!("{}mm {}mm", height, width),
+            },
+            (None, page_size) => {
+                let name = match page_size {
+                    PageSize::Executive => "184.15mm 266.7mm".to_string(),
+                    PageSize::Letter => "letter".to_string(),
+                    PageSize::Legal => "legal".to_string(),
+                    other => format!("{:?}", other),
+                };
+                match self.orientation {
+                    Orientation::Portrait => name,
+                    Orientation::Landscape => format!("{} landscape", name),
+                }
+            }
+        };
+        let margin = self.margin_mm();
+        format!(
+            "@page {{ size: {}; margin: {}mm {}mm {}mm {}mm; }}",
+            size, margin.0, margin.1, margin.2, margin.3
+        )
+    }
+
+    /// Header and footer as css margin boxes, for renderers that support css paged media. The
+    /// chapter is taken from the chapter headings.
+    pub fn running_css(&self, title: &str, author: &str) -> String {
+        let content = |template: &str| -> String {
+            let parts: Vec<String> = template_parts(template)
+                .into_iter()
+                .map(|part| match part {
+                    TemplatePart::Text(text) => css_string(text),
+                    TemplatePart::Placeholder("title") => css_string(title),
+                    TemplatePart::Placeholder("author") => css_string(author),
+                    TemplatePart::Placeholder("date") => css_string(&today()),
+                    TemplatePart::Placeholder("chapter") => "string(chapter)".to_string(),
+                    TemplatePart::Placeholder("pages") => "counter(pages)".to_string(),
+                    TemplatePart::Placeholder(_) => "counter(page)".to_string(),
+                })
+                .collect();
+            if parts.is_empty() {
+                "none".to_string()
+            } else {
+                parts.join(" ")
+            }
+        };
+        format!(
+            "@page {{ @top-center {{ content: {}; font-size: 9pt; }} \
+             @bottom-center {{ content: {}; font-size: 9pt; }} }}\n\
+             @page :first {{ @top-center {{ content: none; }} @bottom-center {{ content: none; }} }}\n\
+             h1.chapter {{ string-set: chapter content(); }}\n",
+            content(&self.header),
+            content(&self.footer)
+        )
+    }
+
+    /// Top, right, bottom and left margin in millimeters.
+    pub fn margin_mm(&self) -> (u32, u32, u32, u32) {
+        (
+            self.margins.top,
+            self.margins.right,
+            self.margins.bottom,
+            self.margins.left + self.gutter,
+        )
+    }
+}
+
+#[cfg(feature = "wkhtmltopdf")]
+impl Layout {
+    pub fn margin(&self) -> Margin {
+        let (top, right, bottom, left) = self.margin_mm();
+        Margin {
+            top: Size::Millimeters(top),
+            bottom: Size::Millimeters(bottom),
+            left: Size::Millimeters(left),
+            right: Size::Millimeters(right),
+        }
+    }
+
+    pub fn page_size(&self) -> wkhtmltopdf::PageSize {
+        if let Some(TrimSize { width, height }) = self.trim_size {
+            return wkhtmltopdf::PageSize::Custom(
+                Size::Millimeters(width),
+                Size::Millimeters(height),
+            );
+        }
+        match self.page_size {
+            PageSize::A4 => wkhtmltopdf::PageSize::A4,
+            PageSize::A5 => wkhtmltopdf::PageSize::A5,
+            PageSize::B5 => wkhtmltopdf::PageSize::B5,
+            PageSize::Letter => wkhtmltopdf::PageSize::Letter,
+            PageSize::Legal => wkhtmltopdf::PageSize::Legal,
+            PageSize::Executive => wkhtmltopdf::PageSize::Executive,
+        }
+    }
+
+    pub fn orientation(&self) -> wkhtmltopdf::Orientation {
+        match self.orientation {
+            Orientation::Portrait => wkhtmltopdf::Orientation::Portrait,
+            Orientation::Landscape => wkhtmltopdf::Orientation::Landscape,
+        }
+    }
+
+    /// Passes the layout on to `builder`, `title` is used when the layout has none.
+    pub fn apply(&self, builder: &mut PdfBuilder, title: &str, author: &str) {
+        builder
+            .page_size(self.page_size())
+            .orientation(self.orientation())
+            .margin(self.margin())
+            .title(self.title.as_ref().map(String::as_str).unwrap_or(title));
+        //wkhtmltopdf fills in its own `[page]`, `[topage]` and `[section]`, the top level
+        //heading of the page
+        let value = |name: &str| match name {
+            "title" => title.to_string(),
+            "author" => author.to_string(),
+            "date" => today(),
+            "chapter" => "[section]".to_string(),
+            "pages" => "[topage]".to_string(),
+            _ => "[page]".to_string(),
+        };
+        let header = fill_template(&self.header, value);
+        let footer = fill_template(&self.footer, value);
+        unsafe {
+            if !header.is_empty() {
+                builder
+                    .object_setting("header.center", header)
+                    .object_setting("header.fontSize", "9");
+            }
+            if !footer.is_empty() {
+                builder
+                    .object_setting("footer.center", footer)
+                    .object_setting("footer.fontSize", "9");
+            }
+        }
+        if let Some(dpi) = self.dpi {
+            builder.dpi(dpi);
+        }
+        if self.grayscale {
+            //the builder has no method for the color mode
+            unsafe {
+                builder.global_setting("colorMode", "Grayscale");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_defaults_to_a4_portrait() {
+        let layout: Layout = serde_json::from_str("{}").unwrap();
+        assert_eq!(layout.page_size, PageSize::A4);
+        assert_eq!(layout.orientation, Orientation::Portrait);
+        assert_eq!(layout.margins, Margins::default());
+        assert_eq!(layout.grayscale, false);
+    }
+
+    #[test]
+    #[cfg(feature = "wkhtmltopdf")]
+    fn custom_trim_size_and_gutter() {
+        let layout: Layout = serde_json::from_str(
+            r#"{
+                "trim_size": {"width": 152, "height": 229},
+                "margins": {"top": 15, "bottom": 15, "left": 12, "right": 12},
+                "gutter": 6
+            }"#,
+        )
+        .unwrap();
+        match layout.page_size() {
+            wkhtmltopdf::PageSize::Custom(Size::Millimeters(152), Size::Millimeters(229)) => {}
+            _ => panic!("trim size should be used as page size"),
+        }
+        match layout.margin().left {
+            Size::Millimeters(left) => assert_eq!(left, 18),
+            _ => panic!("margin should be in millimeters"),
+        }
+    }
+
+    #[test]
+    fn templates_are_split_into_text_and_placeholders() {
+        assert_eq!(
+            template_parts("{title} {x} - {page}/{pages"),
+            vec![
+                TemplatePart::Placeholder("title"),
+                TemplatePart::Text(" {x} - "),
+                TemplatePart::Placeholder("page"),
+                TemplatePart::Text("/{pages"),
+            ]
+        );
+        assert_eq!(
+            fill_template("{chapter}, page {page}", |name| name.to_uppercase()),
+            "CHAPTER, page PAGE"
+        );
+        assert_eq!(Layout::default().footer, "{page}");
+
+        let layout = Layout {
+            header: "{title} \"{chapter}\"".to_string(),
+            footer: String::new(),
+            ..Layout::default()
+        };
+        let css = layout.running_css("Tom & Jerry", "akhil");
+        assert!(css.contains(
+            "@top-center { content: \"Tom & Jerry\" \" \\\"\" string(chapter) \"\\\"\";"
+        ));
+        assert!(css.contains("@bottom-center { content: none;"));
+    }
+
+    #[test]
+    fn page_css_matches_layout() {
+        let layout: Layout = serde_json::from_str(
+            r#"{"page_size": "Letter", "orientation": "Landscape", "gutter": 5}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            layout.page_css(),
+            "@page { size: letter landscape; margin: 20mm 20mm 20mm 25mm; }"
+        );
+
+        let layout: Layout = serde_json::from_str(
+            r#"{"trim_size": {"width": 127, "height": 203}, "orientation": "Landscape"}"#,
+        )
+        .unwrap();
+        assert!(layout.page_css().starts_with("@page { size: 203mm 127mm;"));
+    }
+}
@@ -0,0 +1,433 @@
+use super::blocks::{Block, SCENE_BREAK};
+use super::docx::{self, paragraph, text, PAGE_BREAK, RELS, W_NS};
+use super::render::escape_html;
+use super::Document;
+use crate::book::Author;
+use crate::error::MyError;
+use std::io::{Seek, Write};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+const CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">
+<Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>
+<Default Extension="xml" ContentType="application/xml"/>
+<Override PartName="/word/document.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml"/>
+<Override PartName="/word/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.styles+xml"/>
+<Override PartName="/word/header1.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.header+xml"/>
+<Override PartName="/docProps/core.xml" ContentType="application/vnd.openxmlformats-package.core-properties+xml"/>
+</Types>
+"#;
+
+const DOCUMENT_RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/>
+<Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/header" Target="header1.xml"/>
+</Relationships>
+"#;
+
+// letter paper with inch margins, the title page has no header and the first page of text is page 1
+const SECTION: &str = "<w:sectPr><w:headerReference w:type=\"default\" r:id=\"rId2\"/>\
+                       <w:pgSz w:w=\"12240\" w:h=\"15840\"/>\
+                       <w:pgMar w:top=\"1440\" w:right=\"1440\" w:bottom=\"1440\" w:left=\"1440\" \
+                       w:header=\"720\" w:footer=\"720\" w:gutter=\"0\"/>\
+                       <w:pgNumType w:start=\"0\"/><w:titlePg/></w:sectPr>";
+
+/// Works this long are novels, their word count is rounded to the nearest thousand.
+const NOVEL_WORDS: usize = 40_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ManuscriptFont {
+    Courier,
+    Times,
+}
+
+impl Default for ManuscriptFont {
+    fn default() -> Self {
+        ManuscriptFont::Courier
+    }
+}
+
+impl ManuscriptFont {
+    fn name(self) -> &'static str {
+        match self {
+            ManuscriptFont::Courier => "Courier New",
+            ManuscriptFont::Times => "Times New Roman",
+        }
+    }
+}
+
+/// Standard manuscript format of submissions to agents and publishers: 12pt Courier or Times,
+/// double spaced, with the surname of the author, the title and the page in the header.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct ManuscriptOptions {
+    pub font: ManuscriptFont,
+    /// Title in the header, the full title when not set
+    pub short_title: Option<String>,
+    /// Postal address on the title page, one line per line
+    pub address: Option<String>,
+    pub phone: Option<String>,
+}
+
+// words are counted like word processors do, runs of punctuation on their own don't count
+fn block_words(block: &Block) -> usize {
+    let text: String = match block {
+        Block::Heading(_, spans)
+        | Block::Paragraph(spans)
+        | Block::Quote(spans)
+        | Block::Item(_, spans) => spans.iter().map(|span| span.text.as_str()).collect(),
+        Block::Code(code) => code.clone(),
+        Block::Rule | Block::Image(..) => String::new(),
+    };
+    text.split_whitespace()
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .count()
+}
+
+/// Word count of the title page, rounded to the nearest hundred or, for novels, the nearest
+/// thousand.
+pub fn rounded_word_count(words: usize) -> usize {
+    let step = if words >= NOVEL_WORDS { 1000 } else { 100 };
+    ((words + step / 2) / step * step).max(step)
+}
+
+fn thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut out = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn styles(font: ManuscriptFont) -> String {
+    let mut styles = format!(
+        "<w:docDefaults><w:rPrDefault><w:rPr>\
+         <w:rFonts w:ascii=\"{0}\" w:hAnsi=\"{0}\" w:cs=\"{0}\"/>\
+         <w:sz w:val=\"24\"/></w:rPr></w:rPrDefault></w:docDefaults>\n",
+        font.name()
+    );
+    //every style is 12pt and double spaced, only indents and alignment differ
+    styles.push_str(
+        "<w:style w:type=\"paragraph\" w:default=\"1\" w:styleId=\"Normal\"><w:name w:val=\"Normal\"/>\
+         <w:pPr><w:spacing w:before=\"0\" w:after=\"0\" w:line=\"480\" w:lineRule=\"auto\"/>\
+         <w:ind w:firstLine=\"720\"/></w:pPr></w:style>\n\
+         <w:style w:type=\"paragraph\" w:styleId=\"Contact\"><w:name w:val=\"Contact\"/>\
+         <w:basedOn w:val=\"Normal\"/><w:pPr><w:tabs><w:tab w:val=\"right\" w:pos=\"9360\"/></w:tabs>\
+         <w:spacing w:line=\"240\" w:lineRule=\"auto\"/><w:ind w:firstLine=\"0\"/></w:pPr></w:style>\n\
+         <w:style w:type=\"paragraph\" w:styleId=\"Title\"><w:name w:val=\"Title\"/>\
+         <w:basedOn w:val=\"Normal\"/><w:pPr><w:spacing w:before=\"4320\"/>\
+         <w:ind w:firstLine=\"0\"/><w:jc w:val=\"center\"/></w:pPr></w:style>\n\
+         <w:style w:type=\"paragraph\" w:styleId=\"Byline\"><w:name w:val=\"Byline\"/>\
+         <w:basedOn w:val=\"Normal\"/><w:pPr><w:ind w:firstLine=\"0\"/><w:jc w:val=\"center\"/></w:pPr></w:style>\n\
+         <w:style w:type=\"paragraph\" w:styleId=\"Header\"><w:name w:val=\"header\"/>\
+         <w:basedOn w:val=\"Normal\"/><w:pPr><w:spacing w:line=\"240\" w:lineRule=\"auto\"/>\
+         <w:ind w:firstLine=\"0\"/><w:jc w:val=\"right\"/></w:pPr></w:style>\n\
+         <w:style w:type=\"paragraph\" w:styleId=\"SceneBreak\"><w:name w:val=\"Scene Break\"/>\
+         <w:basedOn w:val=\"Normal\"/><w:pPr><w:ind w:firstLine=\"0\"/><w:jc w:val=\"center\"/></w:pPr></w:style>\n\
+         <w:style w:type=\"paragraph\" w:styleId=\"Quote\"><w:name w:val=\"Quote\"/>\
+         <w:basedOn w:val=\"Normal\"/><w:pPr><w:ind w:left=\"720\" w:right=\"720\" w:firstLine=\"0\"/></w:pPr></w:style>\n\
+         <w:style w:type=\"paragraph\" w:styleId=\"ListParagraph\"><w:name w:val=\"List Paragraph\"/>\
+         <w:basedOn w:val=\"Normal\"/><w:pPr><w:ind w:left=\"720\" w:hanging=\"360\"/></w:pPr></w:style>\n\
+         <w:style w:type=\"paragraph\" w:styleId=\"SourceCode\"><w:name w:val=\"Source Code\"/>\
+         <w:basedOn w:val=\"Normal\"/><w:pPr><w:ind w:firstLine=\"0\"/></w:pPr></w:style>\n",
+    );
+    for i in 0..6 {
+        //chapters start on a new page, a third of the way down
+        let (chapter, align) = if i == 0 {
+            (
+                "<w:pageBreakBefore/><w:spacing w:before=\"2880\"/>",
+                "<w:jc w:val=\"center\"/>",
+            )
+        } else {
+            ("", "")
+        };
+        styles.push_str(&format!(
+            "<w:style w:type=\"paragraph\" w:styleId=\"Heading{0}\"><w:name w:val=\"heading {0}\"/>\
+             <w:basedOn w:val=\"Normal\"/><w:next w:val=\"Normal\"/>\
+             <w:pPr><w:keepNext/>{1}<w:ind w:firstLine=\"0\"/>{2}<w:outlineLvl w:val=\"{3}\"/></w:pPr></w:style>\n",
+            i + 1,
+            chapter,
+            align,
+            i
+        ));
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<w:styles {}>\n{}</w:styles>\n",
+        W_NS, styles
+    )
+}
+
+fn header(surname: &str, title: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<w:hdr {}>\n\
+         <w:p><w:pPr><w:pStyle w:val=\"Header\"/></w:pPr>\
+         <w:r><w:t xml:space=\"preserve\">{} / {} / </w:t></w:r>\
+         <w:fldSimple w:instr=\" PAGE \"><w:r><w:t>1</w:t></w:r></w:fldSimple></w:p>\n</w:hdr>\n",
+        W_NS,
+        escape_html(surname),
+        escape_html(title)
+    )
+}
+
+// contact details at the top left with the word count on the right of the first line, title
+// and byline half way down
+fn title_page(
+    doc: &Document,
+    options: &ManuscriptOptions,
+    author: Option<&Author>,
+    byline: &str,
+    words: usize,
+) -> String {
+    let mut contact = vec![author
+        .map_or(byline, |author| author.name.as_str())
+        .to_string()];
+    if let Some(ref address) = options.address {
+        contact.extend(address.lines().map(str::trim).map(String::from));
+    }
+    contact.extend(options.phone.clone());
+    contact.extend(author.map(|author| author.email.clone()));
+    contact.retain(|line| !line.trim().is_empty());
+    if contact.is_empty() {
+        contact.push(String::new());
+    }
+    contact[0] = format!(
+        "{}\tabout {} words",
+        contact[0],
+        thousands(rounded_word_count(words))
+    );
+
+    let mut body: String = contact
+        .iter()
+        .map(|line| paragraph("Contact", &[text(line)]))
+        .collect();
+    body.push_str(&paragraph("Title", &[text(&doc.meta.title)]));
+    if !byline.is_empty() {
+        body.push_str(&paragraph("Byline", &[text(&format!("by {}", byline))]));
+    }
+    body
+}
+
+/// `word/document.xml` and `word/header1.xml` of the manuscript.
+fn document_and_header(
+    doc: &Document,
+    options: &ManuscriptOptions,
+    author: Option<&Author>,
+) -> (String, String) {
+    //the byline is the name the book is published under, the contact the configured author
+    let byline = if doc.authors.is_empty() {
+        author.map_or("", |author| author.name.as_str()).to_string()
+    } else {
+        doc.authors.join(", ")
+    };
+    let surname = doc
+        .authors
+        .first()
+        .map(String::as_str)
+        .unwrap_or(&byline)
+        .split_whitespace()
+        .last()
+        .unwrap_or("");
+
+    let chapters: Vec<Vec<Vec<Block>>> = doc
+        .chapters
+        .iter()
+        .map(|chapter| {
+            chapter
+                .sections
+                .iter()
+                .map(|s| doc.section_blocks(s))
+                .collect()
+        })
+        .collect();
+    let words = chapters.iter().flatten().flatten().map(block_words).sum();
+
+    let mut body = title_page(doc, options, author, &byline, words);
+    for (chapter, sections) in doc.chapters.iter().zip(&chapters) {
+        if doc.chapter_headings {
+            body.push_str(&paragraph("Heading1", &[text(&chapter.title)]));
+        } else {
+            body.push_str(PAGE_BREAK);
+        }
+        for (i, blocks) in sections.iter().enumerate() {
+            if i > 0 {
+                body.push_str(&paragraph("SceneBreak", &[text(SCENE_BREAK)]));
+            }
+            body.extend(blocks.iter().map(docx::block));
+        }
+    }
+    body.push_str(&paragraph("SceneBreak", &[text("END")]));
+
+    let document = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+         <w:document {} xmlns:r=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships\">\n\
+         <w:body>\n{}\n{}\n</w:body>\n</w:document>\n",
+        W_NS, body, SECTION
+    );
+    let title = options.short_title.as_ref().unwrap_or(&doc.meta.title);
+    (document, header(surname, title))
+}
+
+/// Writes `doc` as a Word document in standard manuscript format. The contact details on the
+/// title page come from `author` and `options`, the byline and the surname in the header from
+/// the authors of the book.
+pub fn write_manuscript<W: Write + Seek>(
+    doc: &Document,
+    options: &ManuscriptOptions,
+    author: Option<&Author>,
+    out: W,
+) -> Result<(), MyError> {
+    let (document, header) = document_and_header(doc, options, author);
+    let mut zip = ZipWriter::new(out);
+    let zip_options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let parts = [
+        ("[Content_Types].xml", CONTENT_TYPES.to_string()),
+        ("_rels/.rels", RELS.to_string()),
+        ("docProps/core.xml", docx::core(doc)),
+        ("word/_rels/document.xml.rels", DOCUMENT_RELS.to_string()),
+        ("word/document.xml", document),
+        ("word/styles.xml", styles(options.font)),
+        ("word/header1.xml", header),
+    ];
+    for (name, content) in parts.iter() {
+        zip.start_file(*name, zip_options)?;
+        zip.write_all(content.as_bytes())?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::{AuthType, BookMeta, Chapter, Section};
+    use std::io::{Cursor, Read};
+
+    fn section(id: &str, content: &str) -> Section {
+        Section {
+            id: id.to_string(),
+            name: id.to_string(),
+            content: content.to_string(),
+            synopsis: String::new(),
+        }
+    }
+
+    fn doc() -> Document {
+        Document {
+            id: "root".to_string(),
+            meta: BookMeta::new("Tom & Jerry"),
+            authors: vec!["Akhil Kumar".to_string()],
+            chapters: vec![
+                Chapter {
+                    id: "c1".to_string(),
+                    title: "Chap1".to_string(),
+                    sections: vec![
+                        section(
+                            "s1",
+                            "It was **dark** -- and *cold*.\n\n***\n\nThen `light`.",
+                        ),
+                        section("s2", "The end"),
+                    ],
+                },
+                Chapter {
+                    id: "c2".to_string(),
+                    title: "Chap2".to_string(),
+                    sections: vec![section("s3", "# Part\n\nOne more")],
+                },
+            ],
+            chapter_headings: true,
+            ..Document::default()
+        }
+    }
+
+    fn author() -> Author {
+        Author {
+            name: "Akhil K. Kumar".to_string(),
+            email: "akhil@example.com".to_string(),
+            token: "token".to_string(),
+            auth: AuthType::SSHAgent,
+        }
+    }
+
+    #[test]
+    fn word_counts_are_rounded() {
+        assert_eq!(rounded_word_count(12), 100);
+        assert_eq!(rounded_word_count(5_349), 5_300);
+        assert_eq!(rounded_word_count(5_350), 5_400);
+        assert_eq!(rounded_word_count(93_600), 94_000);
+        assert_eq!(thousands(94_000), "94,000");
+        assert_eq!(thousands(100), "100");
+        assert_eq!(thousands(1_234_567), "1,234,567");
+
+        let blocks = doc().section_blocks(&section("s", "It was **dark** -- and *cold*."));
+        assert_eq!(blocks.iter().map(block_words).sum::<usize>(), 5);
+    }
+
+    #[test]
+    fn manuscript_has_a_title_page_scene_breaks_and_an_end() {
+        let options = ManuscriptOptions {
+            address: Some("1 Main St\nSpringfield".to_string()),
+            ..ManuscriptOptions::default()
+        };
+        let (document, header) = document_and_header(&doc(), &options, Some(&author()));
+        let contact = document.find(">Akhil K. Kumar\tabout 100 words<").unwrap();
+        let address = document.find(">Springfield<").unwrap();
+        let email = document.find(">akhil@example.com<").unwrap();
+        let title = document.find(">Tom &amp; Jerry<").unwrap();
+        let byline = document.find(">by Akhil Kumar<").unwrap();
+        let chapter = document.find(">Chap1<").unwrap();
+        let end = document.find(">END<").unwrap();
+        assert!(contact < address && address < email && email < title);
+        assert!(title < byline && byline < chapter && chapter < end);
+        assert!(document[end..].starts_with(">END</w:t></w:r></w:p>\n<w:sectPr>"));
+
+        //the rule inside the first section and the break between sections are both scene breaks
+        let breaks = document
+            .matches("<w:pStyle w:val=\"SceneBreak\"/></w:pPr><w:r><w:t xml:space=\"preserve\">#<")
+            .count();
+        assert_eq!(breaks, 2);
+        //a heading inside a section stays below the chapter
+        assert!(document.contains(
+            "<w:pStyle w:val=\"Heading2\"/></w:pPr><w:r><w:t xml:space=\"preserve\">Part<"
+        ));
+        assert!(header
+            .contains(">Kumar / Tom &amp; Jerry / </w:t></w:r><w:fldSimple w:instr=\" PAGE \">"));
+    }
+
+    #[test]
+    fn manuscript_without_author_config_and_with_options() {
+        let options = ManuscriptOptions {
+            font: ManuscriptFont::Times,
+            short_title: Some("Tom".to_string()),
+            ..ManuscriptOptions::default()
+        };
+        let mut doc = doc();
+        doc.chapter_headings = false;
+        let (document, header) = document_and_header(&doc, &options, None);
+        assert!(document.contains(">Akhil Kumar\tabout 100 words<"));
+        assert!(!document.contains(">Chap1<"));
+        assert_eq!(document.matches(PAGE_BREAK).count(), 2);
+        assert!(header.contains(">Kumar / Tom / <"));
+        assert!(styles(options.font).contains("w:ascii=\"Times New Roman\""));
+
+        let mut buf = Cursor::new(Vec::new());
+        write_manuscript(&doc, &options, None, &mut buf).unwrap();
+        let mut zip = zip::ZipArchive::new(buf).unwrap();
+        let mut types = String::new();
+        zip.by_name("[Content_Types].xml")
+            .unwrap()
+            .read_to_string(&mut types)
+            .unwrap();
+        for i in 0..zip.len() {
+            let name = zip.by_index(i).unwrap().name().to_string();
+            if name.ends_with(".xml") && name != "[Content_Types].xml" {
+                assert!(types.contains(&format!("PartName=\"/{}\"", name)));
+            }
+        }
+    }
+}
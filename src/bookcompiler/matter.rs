@@ -0,0 +1,220 @@
+use super::render::{escape_html, section_to_html};
+use super::Document;
+use chrono::{Datelike, Utc};
+
+pub const CONTENTS_TITLE: &str = "Contents";
+pub const ABOUT_AUTHOR_TITLE: &str = "About the Author";
+
+/// Pages generated around the chapters from the metadata of the book, each one is only added
+/// when the compile asks for it. Html and latex always open with the title and contents.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct Matter {
+    pub title_page: bool,
+    /// Copyright notice with the rights, publisher and isbn of the book
+    pub copyright: bool,
+    /// Only added when the book has a dedication
+    pub dedication: bool,
+    /// Table of contents, with page numbers in pdfs and links in epub
+    pub toc: bool,
+    /// Only added when the book has an about the author text, it follows the last chapter
+    pub about_author: bool,
+}
+
+fn non_empty(text: &Option<String>) -> Option<&str> {
+    text.as_ref()
+        .map(|text| text.trim())
+        .filter(|text| !text.is_empty())
+}
+
+/// Lines of the copyright page, none when it was left out.
+pub fn copyright(doc: &Document) -> Option<Vec<String>> {
+    if !doc.matter.copyright {
+        return None;
+    }
+    //books without commits or a year in their metadata are copyrighted this year
+    let year = doc.year.unwrap_or_else(|| Utc::now().year());
+    let mut notice = format!("Copyright \u{a9} {}", year);
+    if !doc.authors.is_empty() {
+        notice.push(' ');
+        notice.push_str(&doc.authors.join(", "));
+    }
+    let mut lines = vec![notice];
+    if let Some(rights) = non_empty(&doc.meta.rights) {
+        lines.push(rights.to_string());
+    }
+    if let Some(publisher) = non_empty(&doc.meta.publisher) {
+        lines.push(format!("Published by {}", publisher));
+    }
+    if let Some(isbn) = non_empty(&doc.meta.isbn) {
+        lines.push(format!("ISBN {}", isbn));
+    }
+    Some(lines)
+}
+
+/// The dedication in the markup of the compile.
+pub fn dedication(doc: &Document) -> Option<&str> {
+    if doc.matter.dedication {
+        non_empty(&doc.meta.dedication)
+    } else {
+        None
+    }
+}
+
+/// The about the author text in the markup of the compile.
+pub fn about_author(doc: &Document) -> Option<&str> {
+    if doc.matter.about_author {
+        non_empty(&doc.meta.about_author)
+    } else {
+        None
+    }
+}
+
+pub fn title_html(doc: &Document) -> String {
+    let mut out = format!(
+        "<header class=\"title-page\">\n<h1>{}</h1>\n",
+        escape_html(&doc.meta.title)
+    );
+    if let Some(ref subtitle) = doc.meta.subtitle {
+        out.push_str(&format!("<h2>{}</h2>\n", escape_html(subtitle)));
+    }
+    if !doc.authors.is_empty() {
+        out.push_str(&format!(
+            "<p class=\"authors\">{}</p>\n",
+            escape_html(&doc.authors.join(", "))
+        ));
+    }
+    out.push_str("</header>\n");
+    out
+}
+
+pub fn copyright_html(doc: &Document) -> Option<String> {
+    let lines = copyright(doc)?;
+    let mut out = String::from("<section class=\"copyright\">\n");
+    for line in lines {
+        out.push_str(&format!("<p>{}</p>\n", escape_html(&line)));
+    }
+    out.push_str("</section>\n");
+    Some(out)
+}
+
+pub fn dedication_html(doc: &Document) -> Option<String> {
+    dedication(doc).map(|dedication| {
+        format!(
+            "<section class=\"dedication\">\n{}</section>\n",
+            section_to_html(dedication, doc.markup, doc.typography)
+        )
+    })
+}
+
+// links to every chapter, `href` decides whether they point to anchors or separate pages
+pub fn contents_html(doc: &Document, href: impl Fn(usize) -> String) -> String {
+    let mut out = format!("<nav class=\"toc\">\n<h1>{}</h1>\n<ol>\n", CONTENTS_TITLE);
+    for (i, chapter) in doc.chapters.iter().enumerate() {
+        out.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            href(i),
+            escape_html(&chapter.title)
+        ));
+    }
+    out.push_str("</ol>\n</nav>\n");
+    out
+}
+
+pub fn about_author_html(doc: &Document) -> Option<String> {
+    about_author(doc).map(|about| {
+        format!(
+            "<section class=\"about-author\">\n<h1>{}</h1>\n{}</section>\n",
+            ABOUT_AUTHOR_TITLE,
+            section_to_html(about, doc.markup, doc.typography)
+        )
+    })
+}
+
+/// Title page, copyright, dedication and table of contents, in that order.
+pub fn front_matter_html(doc: &Document, href: impl Fn(usize) -> String) -> String {
+    let mut out = String::new();
+    if doc.matter.title_page {
+        out.push_str(&title_html(doc));
+    }
+    out.push_str(&copyright_html(doc).unwrap_or_default());
+    out.push_str(&dedication_html(doc).unwrap_or_default());
+    if doc.matter.toc {
+        out.push_str(&contents_html(doc, href));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::BookMeta;
+
+    fn doc() -> Document {
+        let mut meta = BookMeta::new("Tom & Jerry");
+        meta.rights = Some("All rights reserved.".to_string());
+        meta.isbn = Some("978-3-16-148410-0".to_string());
+        meta.dedication = Some("For *Spike*".to_string());
+        meta.about_author = Some("  ".to_string());
+        Document {
+            id: "root".to_string(),
+            meta,
+            authors: vec!["akhil".to_string(), "jane".to_string()],
+            matter: Matter {
+                title_page: true,
+                copyright: true,
+                dedication: true,
+                toc: true,
+                about_author: true,
+            },
+            ..Document::default()
+        }
+    }
+
+    #[test]
+    fn matter_comes_from_the_metadata() {
+        let mut doc = doc();
+        let lines = copyright(&doc).unwrap();
+        assert!(lines[0].starts_with("Copyright \u{a9} 2"));
+        assert!(lines[0].ends_with(" akhil, jane"));
+        doc.year = Some(2019);
+        assert_eq!(
+            copyright(&doc).unwrap()[0],
+            "Copyright \u{a9} 2019 akhil, jane"
+        );
+        assert_eq!(
+            &lines[1..],
+            &["All rights reserved.", "ISBN 978-3-16-148410-0"]
+        );
+        assert_eq!(
+            dedication_html(&doc).unwrap(),
+            "<section class=\"dedication\">\n<p>For <em>Spike</em></p>\n</section>\n"
+        );
+        //blank texts don't get a page
+        assert_eq!(about_author(&doc), None);
+
+        doc.matter.copyright = false;
+        doc.matter.dedication = false;
+        assert_eq!(copyright(&doc), None);
+        assert_eq!(dedication(&doc), None);
+    }
+
+    #[test]
+    fn front_matter_leaves_out_what_was_not_asked_for() {
+        let mut doc = doc();
+        let html = front_matter_html(&doc, |i| format!("#chapter-{}", i + 1));
+        let title = html.find("class=\"title-page\"").unwrap();
+        let copyright = html.find("class=\"copyright\"").unwrap();
+        let dedication = html.find("class=\"dedication\"").unwrap();
+        let toc = html.find("class=\"toc\"").unwrap();
+        assert!(title < copyright && copyright < dedication && dedication < toc);
+
+        doc.matter = Matter {
+            copyright: true,
+            ..Matter::default()
+        };
+        let html = front_matter_html(&doc, |i| format!("#chapter-{}", i + 1));
+        assert!(!html.contains("title-page") && !html.contains("toc"));
+        assert!(html.contains("class=\"copyright\""));
+    }
+}
@@ -0,0 +1,1149 @@
+use super::blocks::{section_blocks, Block, Span, SCENE_BREAK};
+use super::layout::{fill_template, today};
+use super::matter::{self, ABOUT_AUTHOR_TITLE, CONTENTS_TITLE};
+use super::themes::Typesetting;
+use super::{Document, Layout};
+use crate::error::MyError;
+use printpdf::image::{self, DynamicImage, GenericImageView};
+use printpdf::indices::PdfPageIndex;
+use printpdf::{
+    Color, CurTransMat, Greyscale, Image, IndirectFontRef, Mm, PdfDocument, PdfDocumentReference,
+    PdfLayerReference,
+};
+use rusttype::{Font, Scale};
+use std::fs;
+use std::io::BufWriter;
+use std::mem;
+use std::path::{Component, Path, PathBuf};
+
+/// Millimeters in a point.
+const PT: f64 = 25.4 / 72.0;
+/// Size of body text in points, the other sizes are scaled with the font size of the theme.
+const BODY_SIZE: f64 = 11.0;
+/// Position of the baseline from the top of a line relative to its height.
+const BASELINE: f64 = 0.75;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Face {
+    Regular,
+    Italic,
+    Bold,
+    BoldItalic,
+    Mono,
+}
+
+//DejaVu Serif and Sans Mono, see assets/fonts/LICENSE. Indexed by `Face`.
+const FONT_FILES: [&[u8]; 5] = [
+    include_bytes!("../../assets/fonts/DejaVuSerif.ttf"),
+    include_bytes!("../../assets/fonts/DejaVuSerif-Italic.ttf"),
+    include_bytes!("../../assets/fonts/DejaVuSerif-Bold.ttf"),
+    include_bytes!("../../assets/fonts/DejaVuSerif-BoldItalic.ttf"),
+    include_bytes!("../../assets/fonts/DejaVuSansMono.ttf"),
+];
+
+impl Face {
+    fn of(span: &Span) -> Face {
+        match (span.code, span.strong, span.emphasis) {
+            (true, _, _) => Face::Mono,
+            (false, true, true) => Face::BoldItalic,
+            (false, true, false) => Face::Bold,
+            (false, false, true) => Face::Italic,
+            (false, false, false) => Face::Regular,
+        }
+    }
+
+    // the face a font asset of a theme stands in for, from its file name like `Lora-BoldItalic.ttf`
+    fn of_font_file(rel_path: &str) -> Option<Face> {
+        let path = Path::new(rel_path);
+        let extension = path.extension()?.to_str()?;
+        if !extension.eq_ignore_ascii_case("ttf") {
+            return None;
+        }
+        let stem = path.file_stem()?.to_str()?.to_lowercase();
+        let bold = stem.contains("bold");
+        let italic = stem.contains("italic") || stem.contains("oblique");
+        Some(match (stem.contains("mono"), bold, italic) {
+            (true, _, _) => Face::Mono,
+            (false, true, true) => Face::BoldItalic,
+            (false, true, false) => Face::Bold,
+            (false, false, true) => Face::Italic,
+            (false, false, false) => Face::Regular,
+        })
+    }
+
+    fn bold(self) -> Face {
+        match self {
+            Face::Regular => Face::Bold,
+            Face::Italic => Face::BoldItalic,
+            other => other,
+        }
+    }
+}
+
+// a font is only embedded in the pdf once it is used, with `mono` every face is set in the
+// monospaced font
+struct Fonts {
+    files: Vec<Vec<u8>>,
+    metrics: Vec<Font<'static>>,
+    embedded: Vec<Option<IndirectFontRef>>,
+    mono: bool,
+}
+
+impl Fonts {
+    /// The bundled fonts, with the faces `assets` of a theme have fonts for replaced by those.
+    fn new(mono: bool, assets: &[(String, PathBuf)]) -> Result<Self, MyError> {
+        let mut files: Vec<Vec<u8>> = FONT_FILES.iter().map(|bytes| bytes.to_vec()).collect();
+        let mut replaced = vec![false; FONT_FILES.len()];
+        //assets are sorted by name, the first font of a face wins
+        for (rel_path, path) in assets {
+            if let Some(face) = Face::of_font_file(rel_path) {
+                if !replaced[face as usize] {
+                    files[face as usize] = fs::read(path)?;
+                    replaced[face as usize] = true;
+                }
+            }
+        }
+        let metrics = files
+            .iter()
+            .map(|bytes| Font::from_bytes(bytes.clone()).map_err(|e| e.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Fonts {
+            files,
+            metrics,
+            embedded: vec![None; FONT_FILES.len()],
+            mono,
+        })
+    }
+
+    // with `mono` every face is looked up as the monospaced one
+    fn index(&self, face: Face) -> usize {
+        if self.mono {
+            Face::Mono as usize
+        } else {
+            face as usize
+        }
+    }
+
+    /// Width of `text` in millimeters.
+    fn width(&self, face: Face, size: f64, text: &str) -> f64 {
+        let font = &self.metrics[self.index(face)];
+        let scale = Scale::uniform(size as f32);
+        let points: f32 = text
+            .chars()
+            .map(|c| font.glyph(c).scaled(scale).h_metrics().advance_width)
+            .sum();
+        f64::from(points) * PT
+    }
+
+    fn get(&mut self, pdf: &PdfDocumentReference, face: Face) -> Result<IndirectFontRef, MyError> {
+        let index = self.index(face);
+        let font = &mut self.embedded[index];
+        if font.is_none() {
+            *font = Some(pdf.add_external_font(&self.files[index][..])?);
+        }
+        Ok(font.clone().unwrap())
+    }
+}
+
+/// A word made of pieces in different faces, `width` is in millimeters.
+#[derive(Debug, Default)]
+struct Word {
+    pieces: Vec<(String, Face)>,
+    width: f64,
+}
+
+#[derive(Debug)]
+enum Token {
+    Word(Word),
+    /// A line break inside the paragraph
+    Break,
+}
+
+fn tokens(spans: &[Span], size: f64, bold: bool, fonts: &Fonts) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut word = Word::default();
+    let flush = |word: &mut Word, tokens: &mut Vec<Token>| {
+        if !word.pieces.is_empty() {
+            let mut word = mem::replace(word, Word::default());
+            word.width = word
+                .pieces
+                .iter()
+                .map(|(text, face)| fonts.width(*face, size, text))
+                .sum();
+            tokens.push(Token::Word(word));
+        }
+    };
+
+    for span in spans {
+        let face = if bold {
+            Face::of(span).bold()
+        } else {
+            Face::of(span)
+        };
+        for c in span.text.chars() {
+            if c == '\n' {
+                flush(&mut word, &mut tokens);
+                tokens.push(Token::Break);
+            } else if c.is_whitespace() {
+                flush(&mut word, &mut tokens);
+            } else {
+                match word.pieces.last_mut() {
+                    Some((ref mut text, last)) if *last == face => text.push(c),
+                    _ => word.pieces.push((c.to_string(), face)),
+                }
+            }
+        }
+    }
+    flush(&mut word, &mut tokens);
+    tokens
+}
+
+/// Words that fit on one line, `width` is their natural width in millimeters.
+#[derive(Debug, Default)]
+struct Line {
+    words: Vec<Word>,
+    width: f64,
+    /// False for the last line of a paragraph and lines ending in a line break
+    justify: bool,
+}
+
+// fills every line with as many words as fit, the first line is shortened by `indent`
+fn break_lines(tokens: Vec<Token>, width: f64, indent: f64, space: f64) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut line = Line::default();
+    for token in tokens {
+        match token {
+            Token::Break => lines.push(mem::replace(&mut line, Line::default())),
+            Token::Word(word) => {
+                let available = if lines.is_empty() {
+                    width - indent
+                } else {
+                    width
+                };
+                //a word longer than the line gets a line of its own
+                if !line.words.is_empty() && line.width + space + word.width > available {
+                    line.justify = true;
+                    lines.push(mem::replace(&mut line, Line::default()));
+                }
+                line.width += if line.words.is_empty() {
+                    word.width
+                } else {
+                    space + word.width
+                };
+                line.words.push(word);
+            }
+        }
+    }
+    if !line.words.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Align {
+    Left,
+    Center,
+    Justify,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Style<'a> {
+    size: f64,
+    bold: bool,
+    align: Align,
+    /// Distance from the left and right margin in millimeters
+    indent: (f64, f64),
+    first_line_indent: f64,
+    /// List marker hanging left of the first line
+    label: Option<&'a str>,
+}
+
+impl<'a> Default for Style<'a> {
+    fn default() -> Self {
+        Style {
+            size: BODY_SIZE,
+            bold: false,
+            align: Align::Justify,
+            indent: (0.0, 0.0),
+            first_line_indent: 0.0,
+            label: None,
+        }
+    }
+}
+
+struct Typesetter<'a> {
+    pdf: PdfDocumentReference,
+    fonts: Fonts,
+    layer: PdfLayerReference,
+    page: PdfPageIndex,
+    pages: usize,
+    layout: &'a Layout,
+    typesetting: Typesetting,
+    /// Page width and height in millimeters
+    page_mm: (f64, f64),
+    /// Distance from the top of the page in millimeters, the bottom of the last line or the
+    /// baseline of the text being written
+    y: f64,
+    /// Nothing but the watermark was written on the current page yet
+    blank: bool,
+    /// Layer and chapter of every page, the header and footer are written once the number of
+    /// pages is known
+    running: Vec<(PdfLayerReference, String)>,
+}
+
+impl<'a> Typesetter<'a> {
+    /// `assets` of the theme replace the bundled fonts they have faces for.
+    fn new(
+        title: &str,
+        layout: &'a Layout,
+        typesetting: Typesetting,
+        assets: &[(String, PathBuf)],
+    ) -> Result<Self, MyError> {
+        let page_mm = layout.page_mm();
+        let (pdf, page, layer) = PdfDocument::new(title, Mm(page_mm.0), Mm(page_mm.1), "Text");
+        let layer = pdf.get_page(page).get_layer(layer);
+        let mut typesetter = Typesetter {
+            pdf,
+            fonts: Fonts::new(typesetting.monospace, assets)?,
+            layer: layer.clone(),
+            page,
+            pages: 1,
+            layout,
+            typesetting,
+            page_mm,
+            y: f64::from(layout.margins.top),
+            blank: true,
+            running: vec![(layer, String::new())],
+        };
+        typesetter.watermark()?;
+        Ok(typesetter)
+    }
+
+    fn margins(&self) -> (f64, f64, f64, f64) {
+        let (top, right, bottom, left) = self.layout.margin_mm();
+        (
+            f64::from(top),
+            f64::from(right),
+            f64::from(bottom),
+            f64::from(left),
+        )
+    }
+
+    fn text_width(&self) -> f64 {
+        let (_, right, _, left) = self.margins();
+        self.page_mm.0 - left - right
+    }
+
+    fn text_height(&self) -> f64 {
+        let (top, _, bottom, _) = self.margins();
+        self.page_mm.1 - top - bottom
+    }
+
+    // sizes are given for `BODY_SIZE` and scaled to the font size of the theme
+    fn scale(&self) -> f64 {
+        self.typesetting.font_size / BODY_SIZE
+    }
+
+    // distance between the lines of body text in millimeters
+    fn leading(&self) -> f64 {
+        self.typesetting.font_size * self.typesetting.line_height * PT
+    }
+
+    fn at_top(&self) -> bool {
+        self.y <= self.margins().0
+    }
+
+    fn new_page(&mut self) -> Result<(), MyError> {
+        let (page, layer) = self
+            .pdf
+            .add_page(Mm(self.page_mm.0), Mm(self.page_mm.1), "Text");
+        self.layer = self.pdf.get_page(page).get_layer(layer);
+        self.page = page;
+        self.pages += 1;
+        let chapter = self
+            .running
+            .last()
+            .map(|(_, c)| c.clone())
+            .unwrap_or_default();
+        self.running.push((self.layer.clone(), chapter));
+        self.watermark()?;
+        self.y = self.margins().0;
+        self.blank = true;
+        Ok(())
+    }
+
+    // printpdf can't make text transparent, on white paper a grey below the text looks the same
+    fn watermark(&mut self) -> Result<(), MyError> {
+        let watermark = match self.layout.watermark {
+            Some(ref watermark) if !watermark.text.trim().is_empty() => watermark,
+            _ => return Ok(()),
+        };
+        let text = watermark.text.trim();
+        let (width, height) = self.page_mm;
+        //across the diagonal from the bottom left corner
+        let angle = height.atan2(width).to_degrees();
+        let diagonal = (width * width + height * height).sqrt();
+        let size = (0.6 * diagonal / self.fonts.width(Face::Bold, 1.0, text)).min(144.0);
+        let opacity = watermark.opacity.max(0.0).min(1.0);
+        let font = self.fonts.get(&self.pdf, Face::Bold)?;
+
+        self.layer.save_graphics_state();
+        self.layer
+            .set_fill_color(Color::Greyscale(Greyscale::new(1.0 - opacity, None)));
+        self.layer
+            .set_ctm(CurTransMat::Translate(Mm(width / 2.0), Mm(height / 2.0)));
+        self.layer.set_ctm(CurTransMat::Rotate(angle));
+        let text_width = self.fonts.width(Face::Bold, size, text);
+        self.layer.use_text(
+            text,
+            size,
+            Mm(-text_width / 2.0),
+            Mm(-size * PT * 0.35),
+            &font,
+        );
+        self.layer.restore_graphics_state();
+        Ok(())
+    }
+
+    // every page but the first gets the header and footer, centered in the top and bottom margin
+    fn running_heads(&mut self, title: &str, author: &str) -> Result<(), MyError> {
+        let (top, _, bottom, left) = self.margins();
+        let size = (BODY_SIZE - 2.0) * self.scale();
+        let pages = self.pages;
+        let date = today();
+        let running = mem::replace(&mut self.running, Vec::new());
+        for (i, (layer, chapter)) in running.into_iter().enumerate().skip(1) {
+            let value = |name: &str| match name {
+                "title" => title.to_string(),
+                "author" => author.to_string(),
+                "chapter" => chapter.clone(),
+                "date" => date.clone(),
+                "pages" => pages.to_string(),
+                _ => (i + 1).to_string(),
+            };
+            self.layer = layer;
+            let texts = [
+                (fill_template(&self.layout.header, &value), top / 2.0),
+                (
+                    fill_template(&self.layout.footer, &value),
+                    self.page_mm.1 - bottom / 2.0,
+                ),
+            ];
+            for (text, y) in texts.iter() {
+                if text.trim().is_empty() {
+                    continue;
+                }
+                let width = self.fonts.width(Face::Regular, size, text);
+                self.y = *y;
+                self.text(
+                    text,
+                    Face::Regular,
+                    size,
+                    left + (self.text_width() - width) / 2.0,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    // pages of their own, like chapters, reuse a page nothing was written on
+    fn fresh_page(&mut self) -> Result<(), MyError> {
+        if !self.blank {
+            self.new_page()?;
+        }
+        Ok(())
+    }
+
+    // starts a new page unless `height` millimeters still fit on this one
+    fn ensure(&mut self, height: f64) -> Result<(), MyError> {
+        let (top, _, bottom, _) = self.margins();
+        if !self.at_top() && self.y + height > self.page_mm.1 - bottom {
+            self.new_page()?;
+        }
+        self.y = self.y.max(top);
+        Ok(())
+    }
+
+    // vertical space, dropped at the top of a page
+    fn space(&mut self, height: f64) {
+        if !self.at_top() {
+            self.y += height;
+        }
+    }
+
+    // writes `text` with its baseline at `self.y`
+    fn text(&mut self, text: &str, face: Face, size: f64, x: f64) -> Result<(), MyError> {
+        let font = self.fonts.get(&self.pdf, face)?;
+        self.layer
+            .use_text(text, size, Mm(x), Mm(self.page_mm.1 - self.y), &font);
+        self.blank = false;
+        Ok(())
+    }
+
+    // returns the baseline of the last line
+    fn paragraph(&mut self, spans: &[Span], style: Style) -> Result<f64, MyError> {
+        let size = style.size * self.scale();
+        let leading = size * self.typesetting.line_height * PT;
+        let space = self.fonts.width(Face::Regular, size, " ");
+        let left = self.margins().3 + style.indent.0;
+        let width = self.text_width() - style.indent.0 - style.indent.1;
+        let tokens = tokens(spans, size, style.bold, &self.fonts);
+        let lines = break_lines(tokens, width, style.first_line_indent, space);
+
+        let mut baseline = self.y;
+        for (i, line) in lines.iter().enumerate() {
+            self.ensure(leading)?;
+            let top = self.y;
+            self.y = top + leading * BASELINE;
+            baseline = self.y;
+
+            let indent = if i == 0 { style.first_line_indent } else { 0.0 };
+            let available = width - indent;
+            let align = match style.align {
+                Align::Justify if !self.typesetting.justify => Align::Left,
+                align => align,
+            };
+            let (mut x, gap) = match align {
+                Align::Left => (left + indent, space),
+                Align::Center => (left + (available - line.width) / 2.0, space),
+                Align::Justify if line.justify && line.words.len() > 1 => (
+                    left + indent,
+                    space + (available - line.width) / (line.words.len() - 1) as f64,
+                ),
+                Align::Justify => (left + indent, space),
+            };
+            if let (0, Some(label)) = (i, style.label) {
+                let label_x = left - self.fonts.width(Face::Regular, size, label) - 2.0;
+                self.text(label, Face::Regular, size, label_x)?;
+            }
+            for word in &line.words {
+                for (text, face) in &word.pieces {
+                    self.text(text, *face, size, x)?;
+                    x += self.fonts.width(*face, size, text);
+                }
+                x += gap;
+            }
+            self.y = top + leading;
+        }
+        Ok(baseline)
+    }
+
+    fn centered(&mut self, text: &str, size: f64, bold: bool) -> Result<(), MyError> {
+        let span = Span {
+            text: text.to_string(),
+            ..Span::default()
+        };
+        self.paragraph(
+            &[span],
+            Style {
+                size,
+                bold,
+                align: Align::Center,
+                ..Style::default()
+            },
+        )?;
+        Ok(())
+    }
+
+    fn scene_break(&mut self, text: &str) -> Result<(), MyError> {
+        let leading = self.leading();
+        self.space(leading / 2.0);
+        self.centered(text, BODY_SIZE, false)?;
+        self.space(leading / 2.0);
+        Ok(())
+    }
+
+    fn title_page(&mut self, doc: &Document) -> Result<(), MyError> {
+        self.fresh_page()?;
+        self.y += self.text_height() / 3.0;
+        self.centered(&doc.meta.title, 26.0, true)?;
+        if let Some(ref subtitle) = doc.meta.subtitle {
+            self.space(4.0);
+            self.centered(subtitle, 16.0, false)?;
+        }
+        if !doc.authors.is_empty() {
+            self.space(16.0);
+            self.centered(&doc.authors.join(", "), 14.0, false)?;
+        }
+        Ok(())
+    }
+
+    fn copyright_page(&mut self, lines: &[String]) -> Result<(), MyError> {
+        self.fresh_page()?;
+        let style = Style {
+            size: BODY_SIZE - 2.0,
+            align: Align::Left,
+            ..Style::default()
+        };
+        for line in lines {
+            let span = Span {
+                text: line.to_string(),
+                ..Span::default()
+            };
+            self.paragraph(&[span], style)?;
+            self.space(self.leading() / 2.0);
+        }
+        Ok(())
+    }
+
+    fn dedication_page(&mut self, dedication: &str, doc: &Document) -> Result<(), MyError> {
+        self.fresh_page()?;
+        self.y += self.text_height() / 4.0;
+        let mut indent = false;
+        for block in section_blocks(dedication, doc.markup, doc.typography) {
+            match block {
+                Block::Paragraph(ref spans) => {
+                    self.paragraph(
+                        spans,
+                        Style {
+                            align: Align::Center,
+                            ..Style::default()
+                        },
+                    )?;
+                }
+                ref block => self.block(block, &doc.location, &mut indent)?,
+            }
+        }
+        Ok(())
+    }
+
+    // writes the titles of the chapters and returns where their page numbers go, they are
+    // only known once the chapters are typeset
+    fn contents(&mut self, titles: &[&str]) -> Result<Vec<(PdfLayerReference, f64)>, MyError> {
+        self.chapter(CONTENTS_TITLE, true)?;
+        let mut entries = Vec::new();
+        for title in titles {
+            let span = Span {
+                text: title.to_string(),
+                ..Span::default()
+            };
+            let baseline = self.paragraph(
+                &[span],
+                Style {
+                    align: Align::Left,
+                    indent: (0.0, 15.0),
+                    ..Style::default()
+                },
+            )?;
+            entries.push((self.layer.clone(), baseline));
+            self.space(self.leading() / 3.0);
+        }
+        Ok(entries)
+    }
+
+    fn page_numbers(
+        &mut self,
+        entries: Vec<(PdfLayerReference, f64)>,
+        pages: &[usize],
+    ) -> Result<(), MyError> {
+        let right = self.page_mm.0 - self.margins().1;
+        for ((layer, baseline), page) in entries.into_iter().zip(pages) {
+            let number = page.to_string();
+            let size = BODY_SIZE * self.scale();
+            self.layer = layer;
+            self.y = baseline;
+            let x = right - self.fonts.width(Face::Regular, size, &number);
+            self.text(&number, Face::Regular, size, x)?;
+        }
+        Ok(())
+    }
+
+    fn chapter(&mut self, title: &str, heading: bool) -> Result<(), MyError> {
+        self.fresh_page()?;
+        self.pdf.add_bookmark(title, self.page);
+        if let Some(page) = self.running.last_mut() {
+            page.1 = title.to_string();
+        }
+        if heading {
+            self.y += self.text_height() * 0.15;
+            self.centered(title, 20.0, true)?;
+            self.y += 2.0 * self.leading();
+        }
+        Ok(())
+    }
+
+    // images are printed at the dpi of the layout, scaled down to fit the text area
+    fn image(&mut self, path: &Path) -> Result<(), MyError> {
+        let image = image::open(path)
+            .map_err(|e| MyError(format!("Couldn't read image {}: {}", path.display(), e)))?;
+        let dpi = f64::from(self.layout.dpi.unwrap_or(300));
+        let (px_width, px_height) = image.dimensions();
+        let width = f64::from(px_width) * 25.4 / dpi;
+        let height = f64::from(px_height) * 25.4 / dpi;
+        let scale = (self.text_width() / width)
+            .min(self.text_height() / height)
+            .min(1.0);
+
+        let leading = self.leading();
+        self.space(leading / 2.0);
+        self.ensure(height * scale)?;
+        self.y += height * scale;
+        let x = self.margins().3 + (self.text_width() - width * scale) / 2.0;
+        //pdf images can't have an alpha channel
+        let image = if self.layout.grayscale {
+            DynamicImage::ImageLuma8(image.to_luma8())
+        } else {
+            DynamicImage::ImageRgb8(image.to_rgb8())
+        };
+        Image::from_dynamic_image(&image).add_to_layer(
+            self.layer.clone(),
+            Some(Mm(x)),
+            Some(Mm(self.page_mm.1 - self.y)),
+            None,
+            Some(scale),
+            Some(scale),
+            Some(dpi),
+        );
+        self.space(leading / 2.0);
+        Ok(())
+    }
+
+    // `indent` tells whether a paragraph follows another one and gets a first line indent
+    fn block(&mut self, block: &Block, location: &Path, indent: &mut bool) -> Result<(), MyError> {
+        let leading = self.leading();
+        //themes without first line indents separate paragraphs by space
+        let paragraph_indent = match (*indent, self.typesetting.indent) {
+            (true, true) => 1.5 * self.typesetting.font_size * PT,
+            _ => 0.0,
+        };
+        let paragraph_space = match (*indent, self.typesetting.indent) {
+            (true, false) => leading / 2.0,
+            _ => 0.0,
+        };
+        let had_indent = mem::replace(indent, false);
+        match block {
+            Block::Heading(level, spans) => {
+                let size = match level {
+                    1 => 16.0,
+                    2 => 14.0,
+                    _ => 12.0,
+                };
+                //keeps the heading together with the start of the next paragraph
+                self.ensure(4.0 * leading)?;
+                self.space(leading);
+                self.paragraph(
+                    spans,
+                    Style {
+                        size,
+                        bold: true,
+                        align: Align::Left,
+                        ..Style::default()
+                    },
+                )?;
+                self.space(leading / 2.0);
+            }
+            Block::Paragraph(spans) => {
+                self.space(paragraph_space);
+                self.paragraph(
+                    spans,
+                    Style {
+                        first_line_indent: paragraph_indent,
+                        ..Style::default()
+                    },
+                )?;
+                *indent = true;
+            }
+            Block::Quote(spans) => {
+                self.space(leading / 2.0);
+                self.paragraph(
+                    spans,
+                    Style {
+                        size: BODY_SIZE - 1.0,
+                        indent: (10.0, 10.0),
+                        ..Style::default()
+                    },
+                )?;
+                self.space(leading / 2.0);
+            }
+            Block::Item(number, spans) => {
+                let label = match number {
+                    Some(n) => format!("{}.", n),
+                    None => "\u{2022}".to_string(),
+                };
+                self.paragraph(
+                    spans,
+                    Style {
+                        align: Align::Left,
+                        indent: (8.0, 0.0),
+                        label: Some(&label),
+                        ..Style::default()
+                    },
+                )?;
+            }
+            Block::Code(code) => {
+                let size = (BODY_SIZE - 2.0) * self.scale();
+                let x = self.margins().3 + 5.0;
+                self.space(leading / 2.0);
+                let code_leading = size * self.typesetting.line_height * PT;
+                for line in code.trim_end_matches('\n').lines() {
+                    self.ensure(code_leading)?;
+                    let top = self.y;
+                    self.y = top + code_leading * BASELINE;
+                    self.text(line, Face::Mono, size, x)?;
+                    self.y = top + code_leading;
+                }
+                self.space(leading / 2.0);
+            }
+            Block::Rule => self.scene_break(SCENE_BREAK)?,
+            Block::Image(src, alt) => match local_image(location, src) {
+                Some(path) => self.image(&path)?,
+                //remote images and files outside the book are replaced by their description
+                None => {
+                    let description = Span {
+                        text: alt.clone(),
+                        emphasis: true,
+                        ..Span::default()
+                    };
+                    *indent = had_indent;
+                    self.block(&Block::Paragraph(vec![description]), location, indent)?
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+// images are read from inside the book only, urls and paths leaving the book give none
+fn local_image(location: &Path, src: &str) -> Option<PathBuf> {
+    if let Some(colon) = src.find(':') {
+        //a scheme like `https:` or `data:`
+        if !src[..colon].contains('/') {
+            return None;
+        }
+    }
+    let path = Path::new(src);
+    let inside = path.components().all(|c| match c {
+        Component::Normal(_) | Component::CurDir => true,
+        _ => false,
+    });
+    if inside {
+        Some(location.join(path))
+    } else {
+        None
+    }
+}
+
+/// Typesets `doc` into a pdf at `output` with the fonts of its theme, surrounded by the front and
+/// back matter asked for. The compile stops between chapters once `should_stop` returns true.
+pub fn write_pdf(
+    doc: &Document,
+    layout: &Layout,
+    output: &Path,
+    should_stop: &dyn Fn() -> bool,
+) -> Result<(), MyError> {
+    let title = layout.title.as_ref().unwrap_or(&doc.meta.title);
+    let (typesetting, assets) = match doc.theme {
+        Some(ref theme) => (theme.typesetting, &theme.assets[..]),
+        None => (Typesetting::default(), &[][..]),
+    };
+    let mut typesetter = Typesetter::new(title, layout, typesetting, assets)?;
+    if doc.matter.title_page {
+        typesetter.title_page(doc)?;
+    }
+    if let Some(lines) = matter::copyright(doc) {
+        typesetter.copyright_page(&lines)?;
+    }
+    if let Some(dedication) = matter::dedication(doc) {
+        typesetter.dedication_page(dedication, doc)?;
+    }
+    let contents = if doc.matter.toc {
+        let titles: Vec<&str> = doc.chapters.iter().map(|c| c.title.as_str()).collect();
+        typesetter.contents(&titles)?
+    } else {
+        Vec::new()
+    };
+
+    let mut chapter_pages = Vec::new();
+    for chapter in &doc.chapters {
+        if should_stop() {
+            Err("Compile was cancelled")?
+        }
+        typesetter.chapter(&chapter.title, doc.chapter_headings)?;
+        chapter_pages.push(typesetter.pages);
+        for (i, section) in chapter.sections.iter().enumerate() {
+            if i > 0 {
+                typesetter.scene_break(SCENE_BREAK)?;
+            }
+            let mut indent = false;
+            for block in doc.section_blocks(section) {
+                typesetter.block(&block, &doc.location, &mut indent)?;
+            }
+        }
+    }
+
+    if let Some(about) = matter::about_author(doc) {
+        typesetter.chapter(ABOUT_AUTHOR_TITLE, true)?;
+        let mut indent = false;
+        for block in section_blocks(about, doc.markup, doc.typography) {
+            typesetter.block(&block, &doc.location, &mut indent)?;
+        }
+    }
+    typesetter.page_numbers(contents, &chapter_pages)?;
+    typesetter.running_heads(&doc.meta.title, &doc.authors.join(", "))?;
+
+    typesetter
+        .pdf
+        .save(&mut BufWriter::new(fs::File::create(output)?))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::{BookMeta, Chapter, Section};
+    use crate::bookcompiler::layout::Watermark;
+    use crate::bookcompiler::Matter;
+    use tempdir::TempDir;
+
+    fn span(text: &str) -> Span {
+        Span {
+            text: text.to_string(),
+            ..Span::default()
+        }
+    }
+
+    #[test]
+    fn lines_are_filled_and_only_full_lines_justified() {
+        let fonts = Fonts::new(false, &[]).unwrap();
+        let spans = vec![
+            span("one two "),
+            Span {
+                emphasis: true,
+                ..span("three")
+            },
+            span(", four\nfive"),
+        ];
+        let space = fonts.width(Face::Regular, BODY_SIZE, " ");
+        let width = fonts.width(Face::Regular, BODY_SIZE, "one two three,") + 2.0 * space;
+        let lines = break_lines(tokens(&spans, BODY_SIZE, false, &fonts), width, 0.0, space);
+
+        let words: Vec<Vec<String>> = lines
+            .iter()
+            .map(|line| {
+                line.words
+                    .iter()
+                    .map(|word| word.pieces.iter().map(|(text, _)| text.as_str()).collect())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            words,
+            vec![vec!["one", "two", "three,"], vec!["four"], vec!["five"]]
+        );
+        assert_eq!(
+            lines[0].words[2].pieces,
+            vec![
+                ("three".to_string(), Face::Italic),
+                (",".to_string(), Face::Regular)
+            ]
+        );
+        assert!(lines[0].justify);
+        //a line break ends the line without stretching it
+        assert!(!lines[1].justify && !lines[2].justify);
+    }
+
+    #[test]
+    fn pdf_is_written_with_images() {
+        let temp_dir = TempDir::new("test_pdf").unwrap();
+        let location = temp_dir.path();
+        image::RgbaImage::new(600, 300)
+            .save(location.join("map.png"))
+            .unwrap();
+        let section = |id: &str, content: &str| Section {
+            id: id.to_string(),
+            name: id.to_string(),
+            content: content.to_string(),
+            synopsis: String::new(),
+        };
+        let doc = Document {
+            id: "root".to_string(),
+            location: location.to_path_buf(),
+            meta: BookMeta::new("Tom & Jerry"),
+            authors: vec!["akhil".to_string()],
+            chapters: vec![Chapter {
+                id: "c1".to_string(),
+                title: "Chap1".to_string(),
+                sections: vec![
+                    section("s1", &"It was a *dark* night. ".repeat(200)),
+                    section("s2", "## Morning\n\n![Map](map.png)\n\n1. one\n\n    code"),
+                ],
+            }],
+            ..Document::default()
+        };
+
+        let output = location.join("book.pdf");
+        write_pdf(&doc, &Layout::default(), &output, &|| false).unwrap();
+        assert!(fs::read(&output).unwrap().starts_with(b"%PDF"));
+
+        assert!(write_pdf(&doc, &Layout::default(), &output, &|| true).is_err());
+        let mut missing = doc;
+        missing.chapters[0].sections[1].content = "![Map](nowhere.png)".to_string();
+        let err = write_pdf(&missing, &Layout::default(), &output, &|| false).unwrap_err();
+        assert!(err.to_string().starts_with("Couldn't read image"));
+        //remote images and files outside the book are left out instead of read
+        missing.chapters[0].sections[1].content =
+            "![Map](https://example.com/map.png)\n\n![Key](../map.png)".to_string();
+        write_pdf(&missing, &Layout::default(), &output, &|| false).unwrap();
+    }
+
+    #[test]
+    fn only_images_inside_the_book_are_read() {
+        let book = Path::new("/books/tom");
+        assert_eq!(
+            local_image(book, "img/map.png"),
+            Some(book.join("img/map.png"))
+        );
+        assert_eq!(
+            local_image(book, "./img/a:b.png"),
+            Some(book.join("./img/a:b.png"))
+        );
+        for src in &[
+            "https://example.com/map.png",
+            "data:image/png;base64,AAAA",
+            "/etc/passwd",
+            "../other/map.png",
+            "img/../../map.png",
+        ] {
+            assert_eq!(local_image(book, src), None, "{}", src);
+        }
+    }
+
+    #[test]
+    fn matter_gets_pages_of_its_own() {
+        let layout = Layout::default();
+        let mut doc = Document {
+            id: "root".to_string(),
+            meta: BookMeta::new("Tom & Jerry"),
+            authors: vec!["akhil".to_string()],
+            chapter_headings: false,
+            ..Document::default()
+        };
+        doc.meta.dedication = Some("For *Spike*".to_string());
+        doc.meta.about_author = Some("Akhil writes.".to_string());
+        doc.matter = Matter {
+            title_page: true,
+            copyright: true,
+            dedication: true,
+            toc: true,
+            about_author: true,
+        };
+
+        //a chapter without heading starts on the first page when there is no front matter
+        let mut typesetter = Typesetter::new("Book", &layout, Typesetting::default(), &[]).unwrap();
+        typesetter.chapter("Chap1", false).unwrap();
+        assert_eq!(typesetter.pages, 1);
+
+        let mut typesetter = Typesetter::new("Book", &layout, Typesetting::default(), &[]).unwrap();
+        typesetter.title_page(&doc).unwrap();
+        typesetter.dedication_page("For *Spike*", &doc).unwrap();
+        let entries = typesetter.contents(&["Chap1", "Chap2"]).unwrap();
+        assert_eq!((typesetter.pages, entries.len()), (3, 2));
+        typesetter.chapter("Chap1", true).unwrap();
+        assert_eq!(typesetter.pages, 4);
+        typesetter.page_numbers(entries, &[4, 5]).unwrap();
+
+        let temp_dir = TempDir::new("test_pdf").unwrap();
+        let output = temp_dir.path().join("book.pdf");
+        write_pdf(&doc, &layout, &output, &|| false).unwrap();
+        assert!(fs::read(&output).unwrap().starts_with(b"%PDF"));
+    }
+
+    #[test]
+    fn running_heads_know_the_chapter_of_every_page() {
+        let layout = Layout {
+            header: "{title} - {chapter}".to_string(),
+            watermark: Some(Watermark {
+                text: "DRAFT".to_string(),
+                opacity: 0.2,
+            }),
+            ..Layout::default()
+        };
+        let mut typesetter = Typesetter::new("Book", &layout, Typesetting::default(), &[]).unwrap();
+        //the watermark doesn't count as something written on the page
+        typesetter.chapter("Chap1", true).unwrap();
+        assert_eq!(typesetter.pages, 1);
+        typesetter.new_page().unwrap();
+        typesetter
+            .paragraph(&[span("one")], Style::default())
+            .unwrap();
+        typesetter.chapter("Chap2", false).unwrap();
+        let chapters: Vec<&str> = typesetter
+            .running
+            .iter()
+            .map(|(_, chapter)| chapter.as_str())
+            .collect();
+        assert_eq!(chapters, vec!["Chap1", "Chap1", "Chap2"]);
+
+        typesetter.running_heads("Book", "akhil").unwrap();
+        assert!(typesetter.running.is_empty());
+        let temp_dir = TempDir::new("test_pdf").unwrap();
+        let output = temp_dir.path().join("book.pdf");
+        typesetter
+            .pdf
+            .save(&mut BufWriter::new(fs::File::create(&output).unwrap()))
+            .unwrap();
+        assert!(fs::read(&output).unwrap().starts_with(b"%PDF"));
+    }
+
+    #[test]
+    fn theme_sets_size_spacing_and_font() {
+        let layout = Layout::default();
+        let top = f64::from(layout.margins.top);
+        let text = [span("one")];
+        let mut typesetter = Typesetter::new("Book", &layout, Typesetting::default(), &[]).unwrap();
+        let baseline = typesetter.paragraph(&text, Style::default()).unwrap();
+        assert!((baseline - top - 11.0 * 1.4 * PT * BASELINE).abs() < 1e-9);
+
+        let manuscript = Typesetting {
+            font_size: 12.0,
+            line_height: 2.0,
+            monospace: true,
+            ..Typesetting::default()
+        };
+        let mut typesetter = Typesetter::new("Book", &layout, manuscript, &[]).unwrap();
+        let baseline = typesetter.paragraph(&text, Style::default()).unwrap();
+        assert!((baseline - top - 12.0 * 2.0 * PT * BASELINE).abs() < 1e-9);
+        assert_eq!(
+            typesetter.fonts.width(Face::Italic, 12.0, "one"),
+            Fonts::new(false, &[])
+                .unwrap()
+                .width(Face::Mono, 12.0, "one")
+        );
+    }
+
+    #[test]
+    fn theme_fonts_replace_the_bundled_ones_by_file_name() {
+        assert_eq!(
+            Face::of_font_file("fonts/Lora-Regular.ttf"),
+            Some(Face::Regular)
+        );
+        assert_eq!(
+            Face::of_font_file("Lora-BoldItalic.TTF"),
+            Some(Face::BoldItalic)
+        );
+        assert_eq!(Face::of_font_file("Lora-Oblique.ttf"), Some(Face::Italic));
+        assert_eq!(Face::of_font_file("FiraMono-Bold.ttf"), Some(Face::Mono));
+        assert_eq!(Face::of_font_file("Lora-Bold.woff2"), None);
+
+        let temp_dir = TempDir::new("test_pdf").unwrap();
+        let font = temp_dir.path().join("Typewriter-Italic.ttf");
+        fs::write(&font, FONT_FILES[Face::Mono as usize]).unwrap();
+        let assets = vec![("fonts/Typewriter-Italic.ttf".to_string(), font)];
+        let fonts = Fonts::new(false, &assets).unwrap();
+        let bundled = Fonts::new(false, &[]).unwrap();
+        assert_eq!(
+            fonts.width(Face::Italic, 12.0, "one"),
+            bundled.width(Face::Mono, 12.0, "one")
+        );
+        assert_eq!(
+            fonts.width(Face::Regular, 12.0, "one"),
+            bundled.width(Face::Regular, 12.0, "one")
+        );
+
+        //fonts that can't be read fail the compile instead of falling back silently
+        let broken = temp_dir.path().join("Broken.ttf");
+        fs::write(&broken, "not a font").unwrap();
+        assert!(Fonts::new(false, &[("Broken.ttf".to_string(), broken)]).is_err());
+    }
+}
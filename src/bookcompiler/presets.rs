@@ -0,0 +1,152 @@
+use super::AppState;
+use crate::book::{compile_book, CompileBookRequest, CompileOptions};
+use crate::error::MyError;
+use actix_web::{HttpResponse, Json, Responder, State};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const COMPILE_PRESETS_VERSION: u32 = 1;
+
+/// Named compile options of a book, stored in `.collabook/compile.toml` so that everyone
+/// working on the book produces the same output.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct CompilePresets {
+    version: u32,
+    #[serde(default)]
+    presets: BTreeMap<String, CompileOptions>,
+}
+
+impl Default for CompilePresets {
+    fn default() -> Self {
+        CompilePresets {
+            version: COMPILE_PRESETS_VERSION,
+            presets: BTreeMap::new(),
+        }
+    }
+}
+
+impl CompilePresets {
+    pub fn read(location: &Path) -> Result<Self, MyError> {
+        let path = location.join(".collabook/compile.toml");
+        if !path.exists() {
+            return Ok(CompilePresets::default());
+        }
+        let presets: CompilePresets = toml::from_str(&fs::read_to_string(path)?)?;
+        if presets.version > COMPILE_PRESETS_VERSION {
+            Err("Book was created by a newer version of Collabook")?
+        }
+        Ok(presets)
+    }
+
+    pub fn write(&self, location: &Path) -> Result<(), MyError> {
+        fs::write(
+            location.join(".collabook/compile.toml"),
+            toml::to_string(self)?,
+        )?;
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Result<&CompileOptions, MyError> {
+        self.presets
+            .get(name)
+            .ok_or_else(|| MyError(format!("Compile preset {} not found", name)))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CompilePresetsRequest {
+    location: PathBuf,
+}
+
+pub fn get_compile_presets(info: Json<CompilePresetsRequest>) -> Result<impl Responder, MyError> {
+    let presets = CompilePresets::read(&info.location)?;
+    Ok(HttpResponse::Ok().json(presets.presets))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SaveCompilePresetRequest {
+    location: PathBuf,
+    name: String,
+    options: CompileOptions,
+}
+
+/// Creates the preset or replaces an existing one with the same name.
+pub fn save_compile_preset(
+    info: Json<SaveCompilePresetRequest>,
+) -> Result<impl Responder, MyError> {
+    if !info.location.join(".collabook").exists() {
+        Err("Not a Collabook directory")?
+    }
+    if info.name.trim().is_empty() {
+        Err("Preset name can't be empty")?
+    }
+    let info = info.into_inner();
+    let mut presets = CompilePresets::read(&info.location)?;
+    presets.presets.insert(info.name, info.options);
+    presets.write(&info.location)?;
+    Ok(HttpResponse::Ok().json(presets.presets))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RunCompilePresetRequest {
+    location: PathBuf,
+    name: String,
+    #[serde(default)]
+    output_dir: Option<PathBuf>,
+    #[serde(default)]
+    filename: Option<String>,
+    #[serde(default)]
+    revision: Option<String>,
+}
+
+pub fn run_compile_preset(
+    (state, info): (State<AppState>, Json<RunCompilePresetRequest>),
+) -> Result<impl Responder, MyError> {
+    let options = CompilePresets::read(&info.location)?
+        .get(&info.name)?
+        .clone();
+    let info = info.into_inner();
+    let request = CompileBookRequest {
+        location: info.location,
+        output_dir: info.output_dir,
+        filename: info.filename,
+        revision: info.revision,
+        options,
+    };
+    compile_book((state, Json(request)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bookcompiler::{Layout, OutputFormat};
+    use tempdir::TempDir;
+
+    #[test]
+    fn presets_are_written_and_read() {
+        let temp_dir = TempDir::new("test_presets").unwrap();
+        let location = temp_dir.path();
+        fs::create_dir(location.join(".collabook")).unwrap();
+
+        let mut options: CompileOptions =
+            serde_json::from_str(r#"{"ids": ["a", "b"], "in_binder_order": true}"#).unwrap();
+        //options saved before typography existed keep the text as it was typed
+        assert!(!options.typography);
+        options.format = OutputFormat::Epub;
+        options.layout = serde_json::from_str::<Layout>(
+            r#"{"trim_size": {"width": 152, "height": 229}, "gutter": 6}"#,
+        )
+        .unwrap();
+        save_compile_preset(Json(SaveCompilePresetRequest {
+            location: location.to_path_buf(),
+            name: "Beta reader EPUB".to_string(),
+            options: options.clone(),
+        }))
+        .unwrap();
+
+        let presets = CompilePresets::read(location).unwrap();
+        assert_eq!(presets.get("Beta reader EPUB").unwrap(), &options);
+        assert!(presets.get("Print proof").is_err());
+    }
+}
@@ -0,0 +1,274 @@
+use super::matter;
+use super::typography::{self, Locale};
+use super::{Document, Layout};
+use pulldown_cmark::{html, Event, Parser};
+use std::path::{Component, Path};
+
+/// How the content of a section is written.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Markup {
+    Markdown,
+    Plain,
+}
+
+impl Default for Markup {
+    fn default() -> Self {
+        Markup::Markdown
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// raw html inside a section is shown as text, it would otherwise reach the pdf backend unchecked
+pub fn markdown_to_html(content: &str, typography: Option<Locale>) -> String {
+    let events =
+        typography::markdown_events(Parser::new(content), typography).map(|event| match event {
+            Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+            event => event,
+        });
+    let mut out = String::new();
+    html::push_html(&mut out, events);
+    out
+}
+
+// blank lines separate paragraphs, single newlines are kept as line breaks
+pub fn plain_to_html(content: &str, typography: Option<Locale>) -> String {
+    let content = typography::plain_text(&content.replace("\r\n", "\n"), typography);
+    let mut out = String::new();
+    for paragraph in content.split("\n\n") {
+        let lines: Vec<String> = paragraph
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(escape_html)
+            .collect();
+        if lines.is_empty() {
+            continue;
+        }
+        out.push_str("<p>");
+        out.push_str(&lines.join("<br />\n"));
+        out.push_str("</p>\n");
+    }
+    out
+}
+
+pub fn section_to_html(content: &str, markup: Markup, typography: Option<Locale>) -> String {
+    match markup {
+        Markup::Markdown => markdown_to_html(content, typography),
+        Markup::Plain => plain_to_html(content, typography),
+    }
+}
+
+// front matter gets pages of its own, renderers that support `target-counter` print the page
+// numbers in the table of contents
+const PRINT_STYLE: &str = ".title-page, .copyright, .dedication, nav.toc { page-break-after: always; }\n\
+                           .about-author { page-break-before: always; }\n\
+                           nav.toc a::after { content: leader('.') target-counter(attr(href), page); }\n";
+
+/// A complete html document with one `<section>` per section of the book, grouped by chapter
+/// and surrounded by the front and back matter. It is styled by the theme of the compile.
+pub fn html_document(doc: &Document) -> String {
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str(&format!(
+        "<title>{}</title>\n",
+        escape_html(&doc.meta.title)
+    ));
+    let theme = doc
+        .theme
+        .as_ref()
+        .map(|theme| theme.css_with_file_urls())
+        .unwrap_or_default();
+    out.push_str(&format!("<style>\n{}{}</style>\n", PRINT_STYLE, theme));
+    out.push_str("</head>\n<body>\n");
+    out.push_str(&matter::front_matter_html(doc, |i| {
+        format!("#chapter-{}", i + 1)
+    }));
+    for (i, chapter) in doc.chapters.iter().enumerate() {
+        out.push_str(&format!(
+            "<section class=\"chapter\" id=\"chapter-{}\">\n",
+            i + 1
+        ));
+        if doc.chapter_headings {
+            out.push_str(&format!(
+                "<h1 class=\"chapter\">{}</h1>\n",
+                escape_html(&chapter.title)
+            ));
+        }
+        for section in &chapter.sections {
+            out.push_str(&format!(
+                "<section class=\"section\" id=\"section-{}\">\n",
+                escape_html(&section.id)
+            ));
+            out.push_str(&doc.section_html(section));
+            out.push_str("</section>\n");
+        }
+        out.push_str("</section>\n");
+    }
+    out.push_str(&matter::about_author_html(doc).unwrap_or_default());
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// `html` with relative image srcs pointing at the files of the book at `location`, for
+/// renderers that read the html from somewhere else. Srcs leaving the book are kept as they are.
+fn images_with_file_urls(html: &str, location: &Path) -> String {
+    const IMG_SRC: &str = "<img src=\"";
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find(IMG_SRC) {
+        let (before, after) = rest.split_at(start + IMG_SRC.len());
+        out.push_str(before);
+        let end = match after.find('"') {
+            Some(end) => end,
+            None => {
+                rest = after;
+                break;
+            }
+        };
+        let src = &after[..end];
+        let inside = Path::new(src).components().all(|c| match c {
+            Component::Normal(_) | Component::CurDir => true,
+            _ => false,
+        });
+        if src.is_empty() || src.contains(':') || src.starts_with('#') || !inside {
+            out.push_str(src);
+        } else {
+            let path = location.join(src).to_string_lossy().replace("\\", "/");
+            let slash = if path.starts_with('/') { "" } else { "/" };
+            out.push_str(&format!("file://{}{}", slash, escape_html(&path)));
+        }
+        rest = &after[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The html document handed to pdf renderers, with the running header and footer of `layout`
+/// and its watermark fixed on every page. Images are linked by file url since the renderers run
+/// outside the book.
+pub fn print_document(doc: &Document, layout: &Layout) -> String {
+    let mut style = layout.running_css(&doc.meta.title, &doc.authors.join(", "));
+    let mut watermark = String::new();
+    if let Some(ref mark) = layout.watermark {
+        style.push_str(&format!(
+            ".watermark {{ position: fixed; top: 45%; left: 0; right: 0; text-align: center; \
+             font-size: 72pt; font-weight: bold; color: #000; opacity: {}; z-index: 1000; \
+             -webkit-transform: rotate(-45deg); transform: rotate(-45deg); }}\n",
+            mark.opacity.max(0.0).min(1.0)
+        ));
+        watermark = format!(
+            "<div class=\"watermark\">{}</div>\n",
+            escape_html(&mark.text)
+        );
+    }
+    images_with_file_urls(&html_document(doc), &doc.location)
+        .replacen(
+            "</head>",
+            &format!("<style>\n{}</style>\n</head>", style),
+            1,
+        )
+        .replacen("<body>\n", &format!("<body>\n{}", watermark), 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::{BookMeta, Chapter, Section};
+    use crate::bookcompiler::layout::Watermark;
+    use crate::bookcompiler::Matter;
+
+    #[test]
+    fn markdown_is_rendered_and_raw_html_escaped() {
+        let html = markdown_to_html(
+            "First *paragraph*.\n\nSecond <script>alert(1)</script>",
+            None,
+        );
+        assert_eq!(
+            html,
+            "<p>First <em>paragraph</em>.</p>\n<p>Second &lt;script&gt;alert(1)&lt;/script&gt;</p>\n"
+        );
+    }
+
+    #[test]
+    fn print_images_point_at_the_files_of_the_book() {
+        let html = markdown_to_html(
+            "![map](images/map.png) ![logo](https://example.com/logo.png) ![up](../up.png)",
+            None,
+        );
+        let html = images_with_file_urls(&html, Path::new("/books/Tom & Jerry"));
+        assert!(html.contains("<img src=\"file:///books/Tom &amp; Jerry/images/map.png\""));
+        assert!(html.contains("<img src=\"https://example.com/logo.png\""));
+        assert!(html.contains("<img src=\"../up.png\""));
+    }
+
+    #[test]
+    fn plain_text_is_escaped_and_split_into_paragraphs() {
+        let html = plain_to_html("Tom & Jerry\nsaid <hi>\r\n\r\n\nThe end", None);
+        assert_eq!(
+            html,
+            "<p>Tom &amp; Jerry<br />\nsaid &lt;hi&gt;</p>\n<p>The end</p>\n"
+        );
+    }
+
+    #[test]
+    fn document_wraps_every_section() {
+        let section = |id: &str, content: &str| Section {
+            id: id.to_string(),
+            name: format!("Sec{}", id),
+            content: content.to_string(),
+            synopsis: String::new(),
+        };
+        let doc = Document {
+            id: "root".to_string(),
+            meta: BookMeta::new("Tom & Jerry"),
+            chapters: vec![Chapter {
+                id: "c1".to_string(),
+                title: "Chap1".to_string(),
+                sections: vec![section("1", "one"), section("2", "two")],
+            }],
+            chapter_headings: false,
+            matter: Matter {
+                toc: true,
+                ..Matter::default()
+            },
+            ..Document::default()
+        };
+        let html = html_document(&doc);
+        assert!(html.contains("<title>Tom &amp; Jerry</title>"));
+        assert!(!html.contains("<h1 class=\"chapter\">"));
+        assert!(
+            html.contains("<section class=\"section\" id=\"section-1\">\n<p>one</p>\n</section>")
+        );
+        assert!(
+            html.contains("<section class=\"section\" id=\"section-2\">\n<p>two</p>\n</section>")
+        );
+        assert!(html.contains("<li><a href=\"#chapter-1\">Chap1</a></li>"));
+        assert!(html.contains("<section class=\"chapter\" id=\"chapter-1\">"));
+
+        let layout = Layout {
+            watermark: Some(Watermark {
+                text: "For <Jane>".to_string(),
+                opacity: 0.3,
+            }),
+            ..Layout::default()
+        };
+        let print = print_document(&doc, &layout);
+        assert!(print.contains("@bottom-center { content: counter(page);"));
+        assert!(print.contains("opacity: 0.3;"));
+        assert!(print.contains("<body>\n<div class=\"watermark\">For &lt;Jane&gt;</div>\n"));
+        assert!(!html_document(&doc).contains("watermark"));
+    }
+}
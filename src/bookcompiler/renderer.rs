@@ -0,0 +1,381 @@
+use super::pdf;
+use super::worker::{self, RenderConfig, RenderTask};
+use super::{AppState, Document, OutputFormat};
+use crate::book::APP_INFO;
+use crate::error::MyError;
+use actix_web::{HttpRequest, HttpResponse, Responder};
+use app_dirs::AppDataType;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
+use tempdir::TempDir;
+
+/// A backend that turns the html of a book into a pdf.
+pub trait Renderer: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Whether the backend can be used on this machine, checked once at startup.
+    fn available(&self) -> bool;
+
+    /// Writes `task.output`, stops early when `should_stop` returns true. `task.html` is the
+    /// html of `doc`, backends take whichever suits them.
+    fn render(
+        &self,
+        doc: &Document,
+        task: &RenderTask,
+        should_stop: &dyn Fn() -> bool,
+    ) -> Result<(), MyError>;
+}
+
+/// The typesetter built into Collabook, it needs nothing installed.
+pub struct Builtin;
+
+impl Renderer for Builtin {
+    fn name(&self) -> &str {
+        "builtin"
+    }
+
+    fn available(&self) -> bool {
+        true
+    }
+
+    fn render(
+        &self,
+        doc: &Document,
+        task: &RenderTask,
+        should_stop: &dyn Fn() -> bool,
+    ) -> Result<(), MyError> {
+        pdf::write_pdf(doc, &task.layout, &task.output, should_stop)
+    }
+}
+
+/// The wkhtmltopdf library, run in worker processes.
+pub struct Wkhtmltopdf {
+    pub config: RenderConfig,
+}
+
+impl Renderer for Wkhtmltopdf {
+    fn name(&self) -> &str {
+        "wkhtmltopdf"
+    }
+
+    fn available(&self) -> bool {
+        worker::probe(&self.config).is_ok()
+    }
+
+    fn render(
+        &self,
+        _: &Document,
+        task: &RenderTask,
+        should_stop: &dyn Fn() -> bool,
+    ) -> Result<(), MyError> {
+        worker::render_pdf(&self.config, task, should_stop)
+    }
+}
+
+/// An external program such as weasyprint or pandoc. `{input}` and `{output}` in `args` are
+/// replaced with the path of the html file and the pdf to write.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CommandRenderer {
+    pub name: String,
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Other programs that have to be installed, like the pdf engine of pandoc
+    #[serde(default)]
+    pub requires: Vec<String>,
+    #[serde(skip)]
+    pub config: RenderConfig,
+}
+
+/// Programs pandoc writes pdfs with from html, the first one installed is used.
+const PANDOC_ENGINES: [&str; 5] = [
+    "weasyprint",
+    "wkhtmltopdf",
+    "xelatex",
+    "lualatex",
+    "pdflatex",
+];
+
+impl CommandRenderer {
+    fn new(name: &str, program: &str, args: &[&str]) -> Self {
+        CommandRenderer {
+            name: name.to_string(),
+            program: program.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            requires: Vec::new(),
+            config: RenderConfig::default(),
+        }
+    }
+}
+
+fn find_program(program: &str) -> bool {
+    let program = Path::new(program);
+    if program.components().count() > 1 {
+        return program.is_file();
+    }
+    let paths = match std::env::var_os("PATH") {
+        Some(paths) => paths,
+        None => return false,
+    };
+    std::env::split_paths(&paths).any(|dir| {
+        dir.join(program).is_file()
+            || (cfg!(windows) && dir.join(program).with_extension("exe").is_file())
+    })
+}
+
+// the page setup has no command line equivalent, it is passed as css
+fn with_page_css(html: &str, css: &str) -> String {
+    let style = format!("<style>\n{}\n</style>\n</head>", css);
+    html.replacen("</head>", &style, 1)
+}
+
+impl Renderer for CommandRenderer {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn available(&self) -> bool {
+        find_program(&self.program) && self.requires.iter().all(|program| find_program(program))
+    }
+
+    fn render(
+        &self,
+        _: &Document,
+        task: &RenderTask,
+        should_stop: &dyn Fn() -> bool,
+    ) -> Result<(), MyError> {
+        let dir = TempDir::new("collabook_render")?;
+        let input = dir.path().join("book.html");
+        fs::write(&input, with_page_css(&task.html, &task.layout.page_css()))?;
+
+        let args: Vec<String> = self
+            .args
+            .iter()
+            .map(|arg| {
+                arg.replace("{input}", &input.to_string_lossy())
+                    .replace("{output}", &task.output.to_string_lossy())
+            })
+            .collect();
+        worker::run_supervised(
+            &self.config,
+            || {
+                let mut command = Command::new(&self.program);
+                command.args(&args).current_dir(dir.path());
+                command
+            },
+            &[],
+            should_stop,
+        )
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct RenderersFile {
+    #[serde(default)]
+    renderer: Vec<CommandRenderer>,
+}
+
+/// Command renderers configured in `<config dir>/renderers.toml`, for example
+///
+/// ```toml
+/// [[renderer]]
+/// name = "prince"
+/// program = "prince"
+/// args = ["{input}", "-o", "{output}"]
+/// ```
+fn user_renderers() -> Result<Vec<CommandRenderer>, MyError> {
+    let path = app_dirs::app_root(AppDataType::UserConfig, &APP_INFO)?.join("renderers.toml");
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let file: RenderersFile = toml::from_str(&fs::read_to_string(path)?)?;
+    Ok(file.renderer)
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RendererInfo {
+    pub name: String,
+    pub available: bool,
+}
+
+/// Every known renderer and whether it was found at startup.
+#[derive(Clone, Default)]
+pub struct Renderers {
+    backends: Vec<(Arc<dyn Renderer>, bool)>,
+}
+
+impl Renderers {
+    /// Registers a backend, one with the same name is replaced.
+    pub fn register(&mut self, backend: Arc<dyn Renderer>) {
+        let available = backend.available();
+        if !available {
+            warn!("pdf renderer {} is not available", backend.name());
+        }
+        self.backends.retain(|(b, _)| b.name() != backend.name());
+        self.backends.push((backend, available));
+    }
+
+    /// The built-in typesetter, which is always available and used when no renderer is asked
+    /// for, then wkhtmltopdf, weasyprint, pandoc and the renderers configured by the user.
+    pub fn detect(config: &RenderConfig) -> Self {
+        let mut renderers = Renderers::default();
+        renderers.register(Arc::new(Builtin));
+        renderers.register(Arc::new(Wkhtmltopdf {
+            config: config.clone(),
+        }));
+
+        //pandoc can't write a pdf without an engine, it isn't available when none is installed
+        let engine = PANDOC_ENGINES
+            .iter()
+            .find(|engine| find_program(engine))
+            .unwrap_or(&"pdflatex");
+        let mut pandoc = CommandRenderer::new(
+            "pandoc",
+            "pandoc",
+            &[
+                "--from",
+                "html",
+                "--pdf-engine",
+                engine,
+                "{input}",
+                "-o",
+                "{output}",
+            ],
+        );
+        pandoc.requires.push(engine.to_string());
+        let mut commands = vec![
+            CommandRenderer::new("weasyprint", "weasyprint", &["{input}", "{output}"]),
+            pandoc,
+        ];
+        match user_renderers() {
+            Ok(user) => commands.extend(user),
+            Err(e) => warn!("couldn't read renderers.toml: {}", e),
+        }
+        for mut command in commands {
+            command.config = config.clone();
+            renderers.register(Arc::new(command));
+        }
+        renderers
+    }
+
+    pub fn list(&self) -> Vec<RendererInfo> {
+        self.backends
+            .iter()
+            .map(|(backend, available)| RendererInfo {
+                name: backend.name().to_string(),
+                available: *available,
+            })
+            .collect()
+    }
+
+    /// The renderer called `name`, or the first available one.
+    pub fn get(&self, name: Option<&str>) -> Result<Arc<dyn Renderer>, MyError> {
+        let found = match name {
+            Some(name) => self
+                .backends
+                .iter()
+                .find(|(backend, _)| backend.name() == name)
+                .ok_or_else(|| MyError(format!("Unknown renderer {}", name)))?,
+            None => self
+                .backends
+                .iter()
+                .find(|(_, available)| *available)
+                .ok_or("No pdf renderer is available")?,
+        };
+        match found {
+            (backend, true) => Ok(backend.clone()),
+            (backend, false) => Err(MyError(format!(
+                "Renderer {} is not available",
+                backend.name()
+            ))),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Capabilities {
+    formats: Vec<OutputFormat>,
+    renderers: Vec<RendererInfo>,
+}
+
+/// The output formats this server can produce and its pdf renderers, pdf is only listed when
+/// one of them is available.
+pub fn get_capabilities(req: &HttpRequest<AppState>) -> Result<impl Responder, MyError> {
+    let renderers = req.state().renderers.list();
+    let pdf = renderers.iter().any(|renderer| renderer.available);
+    let formats = OutputFormat::ALL
+        .iter()
+        .cloned()
+        .filter(|&format| format != OutputFormat::Pdf || pdf)
+        .collect();
+    Ok(HttpResponse::Ok().json(Capabilities { formats, renderers }))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::book::BookMeta;
+    use crate::bookcompiler::Layout;
+
+    fn copy_renderer(program: &str) -> CommandRenderer {
+        CommandRenderer::new("copy", program, &["-c", "cp {input} {output}"])
+    }
+
+    #[test]
+    fn command_renderer_gets_html_with_page_css() {
+        let dir = TempDir::new("test_renderer").unwrap();
+        let task = RenderTask {
+            html: "<html><head></head><body>hi</body></html>".to_string(),
+            layout: Layout::default(),
+            title: "Book".to_string(),
+            author: String::new(),
+            output: dir.path().join("book.pdf"),
+        };
+        let doc = Document {
+            id: "root".to_string(),
+            meta: BookMeta::new("Book"),
+            ..Document::default()
+        };
+        copy_renderer("sh").render(&doc, &task, &|| false).unwrap();
+
+        let written = fs::read_to_string(&task.output).unwrap();
+        assert!(written.contains("@page { size: A4; margin: 20mm 20mm 20mm 20mm; }"));
+        assert!(written.ends_with("</head><body>hi</body></html>"));
+    }
+
+    #[test]
+    fn unavailable_renderers_are_reported() {
+        let mut renderers = Renderers::default();
+        renderers.register(Arc::new(copy_renderer("sh")));
+        let mut missing = copy_renderer("collabook-no-such-program");
+        missing.name = "missing".to_string();
+        renderers.register(Arc::new(missing));
+
+        assert_eq!(
+            renderers.list(),
+            vec![
+                RendererInfo {
+                    name: "copy".to_string(),
+                    available: true
+                },
+                RendererInfo {
+                    name: "missing".to_string(),
+                    available: false
+                },
+            ]
+        );
+        assert_eq!(renderers.get(None).unwrap().name(), "copy");
+
+        //a renderer is only available with the programs it requires
+        let mut engineless = copy_renderer("sh");
+        engineless.name = "engineless".to_string();
+        engineless.requires = vec!["collabook-no-such-engine".to_string()];
+        assert!(!engineless.available());
+        engineless.requires = vec!["sh".to_string()];
+        assert!(engineless.available());
+        assert!(renderers.get(Some("missing")).is_err());
+        assert!(renderers.get(Some("prince")).is_err());
+    }
+}
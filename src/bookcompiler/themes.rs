@@ -0,0 +1,343 @@
+use crate::book::APP_INFO;
+use crate::error::MyError;
+use actix_web::{HttpResponse, Json, Responder};
+use app_dirs::AppDataType;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// How the built-in pdf typesetter approximates the css of a theme, read from the optional
+/// `theme.toml` next to `theme.css`. Other than these, the typesetter only takes the `.ttf`
+/// assets of the theme, by file name like `Lora-BoldItalic.ttf` or `FiraMono-Regular.ttf`, and
+/// uses the bundled fonts for faces the theme has none for.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Typesetting {
+    /// Size of body text in points
+    pub font_size: f64,
+    /// Distance between baselines as a multiple of the font size
+    pub line_height: f64,
+    pub justify: bool,
+    /// Set everything in the monospaced font, like a typewritten manuscript
+    pub monospace: bool,
+    /// Indent the first line of paragraphs instead of putting space between them
+    pub indent: bool,
+}
+
+impl Default for Typesetting {
+    fn default() -> Self {
+        Typesetting {
+            font_size: 11.0,
+            line_height: 1.4,
+            justify: true,
+            monospace: false,
+            indent: true,
+        }
+    }
+}
+
+/// Styling of compiled pdf, html and epub output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    pub name: String,
+    pub css: String,
+    /// Fonts and other files the css refers to as (path relative to the theme, file on disk)
+    pub assets: Vec<(String, PathBuf)>,
+    /// Directory of the theme, none for builtin themes
+    pub dir: Option<PathBuf>,
+    pub typesetting: Typesetting,
+}
+
+const MANUSCRIPT_CSS: &str = "\
+body { font-family: \"Courier New\", Courier, monospace; font-size: 12pt; line-height: 2; }
+p { margin: 0; text-indent: 0.5in; text-align: left; }
+h1, h2, h3 { font-size: 12pt; font-weight: normal; }
+h1 { text-align: center; margin: 2in 0 1em; }
+.title-page, .dedication { text-align: center; }
+section.section + section.section::before { content: \"#\"; display: block; text-align: center; }
+";
+
+const NOVEL_CSS: &str = "\
+body { font-family: Georgia, \"Times New Roman\", serif; font-size: 11pt; line-height: 1.4; }
+p { margin: 0; text-indent: 1.5em; text-align: justify; hyphens: auto; }
+section.section > p:first-child, h1 + p { text-indent: 0; }
+h1 { text-align: center; font-weight: normal; font-size: 1.8em; margin: 3em 0 2em; }
+blockquote { margin: 1em 2em; font-style: italic; }
+.title-page, .dedication { text-align: center; }
+section.section + section.section::before { content: \"#\"; display: block; text-align: center; margin: 1em 0; }
+";
+
+const ACADEMIC_CSS: &str = "\
+body { font-family: \"Times New Roman\", Times, serif; font-size: 12pt; line-height: 1.5; }
+p { margin: 0 0 1em; text-align: left; }
+h1 { font-size: 1.6em; margin: 2em 0 1em; }
+h2 { font-size: 1.3em; }
+h3 { font-size: 1.1em; }
+blockquote { margin: 1em 2em; font-size: 0.95em; }
+code, pre { font-family: \"Courier New\", monospace; font-size: 0.9em; }
+";
+
+const BUILTIN: &[(&str, &str, Typesetting)] = &[
+    (
+        "manuscript",
+        MANUSCRIPT_CSS,
+        Typesetting {
+            font_size: 12.0,
+            line_height: 2.0,
+            justify: false,
+            monospace: true,
+            indent: true,
+        },
+    ),
+    (
+        "novel",
+        NOVEL_CSS,
+        Typesetting {
+            font_size: 11.0,
+            line_height: 1.4,
+            justify: true,
+            monospace: false,
+            indent: true,
+        },
+    ),
+    (
+        "academic",
+        ACADEMIC_CSS,
+        Typesetting {
+            font_size: 12.0,
+            line_height: 1.5,
+            justify: false,
+            monospace: false,
+            indent: false,
+        },
+    ),
+];
+
+fn user_themes_dir() -> Result<PathBuf, MyError> {
+    Ok(app_dirs::app_root(AppDataType::UserConfig, &APP_INFO)?.join("themes"))
+}
+
+fn book_themes_dir(location: &Path) -> PathBuf {
+    location.join(".collabook/themes")
+}
+
+impl Theme {
+    fn builtin(name: &str) -> Option<Self> {
+        BUILTIN
+            .iter()
+            .find(|(n, _, _)| *n == name)
+            .map(|(n, css, typesetting)| Theme {
+                name: n.to_string(),
+                css: css.to_string(),
+                assets: Vec::new(),
+                dir: None,
+                typesetting: *typesetting,
+            })
+    }
+
+    /// Reads `dir/theme.css`, `dir/theme.toml` if it exists and lists every other file as an
+    /// asset.
+    pub fn from_dir(name: &str, dir: &Path) -> Result<Self, MyError> {
+        let css_path = dir.join("theme.css");
+        if !css_path.is_file() {
+            Err(MyError(format!("Theme {} has no theme.css", name)))?
+        }
+        let toml_path = dir.join("theme.toml");
+        let typesetting = if toml_path.is_file() {
+            toml::from_str(&fs::read_to_string(toml_path)?)?
+        } else {
+            Typesetting::default()
+        };
+
+        let mut assets = Vec::new();
+        for entry in WalkDir::new(dir)
+            .min_depth(1)
+            .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+            .into_iter()
+            .filter_entry(|e| {
+                !e.file_name()
+                    .to_str()
+                    .map(|s| s.starts_with('.'))
+                    .unwrap_or(false)
+            })
+        {
+            let entry = entry.map_err(|e| e.to_string())?;
+            let rel_path = entry
+                .path()
+                .strip_prefix(dir)?
+                .to_str()
+                .ok_or("Filename contains invalid utf-8")?
+                .replace("\\", "/");
+            if entry.file_type().is_file() && rel_path != "theme.css" && rel_path != "theme.toml" {
+                assets.push((rel_path, entry.path().to_path_buf()));
+            }
+        }
+
+        Ok(Theme {
+            name: name.to_string(),
+            css: fs::read_to_string(css_path)?,
+            assets,
+            dir: Some(dir.to_path_buf()),
+            typesetting,
+        })
+    }
+
+    /// Themes of the book in `.collabook/themes/<name>` take precedence over the ones in
+    /// `<config dir>/themes/<name>`, which take precedence over builtin ones.
+    pub fn find(location: &Path, name: &str) -> Result<Self, MyError> {
+        if Path::new(name).file_name() != Some(OsStr::new(name)) {
+            Err(MyError(format!("Theme {} not found", name)))?
+        }
+        for dir in &[book_themes_dir(location), user_themes_dir()?] {
+            let dir = dir.join(name);
+            if dir.is_dir() {
+                return Theme::from_dir(name, &dir);
+            }
+        }
+        Theme::builtin(name).ok_or_else(|| MyError(format!("Theme {} not found", name)))
+    }
+
+    pub fn names(location: &Path) -> Result<Vec<String>, MyError> {
+        let mut names: Vec<String> = BUILTIN.iter().map(|(n, _, _)| n.to_string()).collect();
+        for dir in &[user_themes_dir()?, book_themes_dir(location)] {
+            if !dir.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                if entry.file_type()?.is_dir() {
+                    if let Some(name) = entry.file_name().to_str() {
+                        if !names.iter().any(|n| n == name) {
+                            names.push(name.to_string());
+                        }
+                    }
+                }
+            }
+        }
+        Ok(names)
+    }
+
+    /// The css with relative `url()`s pointing at the files of the theme, for renderers that
+    /// read the html from somewhere else.
+    pub fn css_with_file_urls(&self) -> String {
+        let dir = match self.dir {
+            Some(ref dir) => dir,
+            None => return self.css.clone(),
+        };
+        let mut out = String::with_capacity(self.css.len());
+        let mut rest = self.css.as_str();
+        while let Some(start) = rest.find("url(") {
+            let (before, after) = rest.split_at(start + "url(".len());
+            out.push_str(before);
+            let end = match after.find(')') {
+                Some(end) => end,
+                None => {
+                    rest = after;
+                    break;
+                }
+            };
+            let url = after[..end].trim().trim_matches(|c| c == '"' || c == '\'');
+            if url.contains(':') || url.starts_with('/') || url.starts_with('#') {
+                out.push_str(&after[..end]);
+            } else {
+                let path = dir.join(url).to_string_lossy().replace("\\", "/");
+                let slash = if path.starts_with('/') { "" } else { "/" };
+                out.push_str(&format!("\"file://{}{}\"", slash, path));
+            }
+            rest = &after[end..];
+        }
+        out.push_str(rest);
+        out
+    }
+
+    /// Copies the assets into `dir`, keeping their paths relative to the css.
+    pub fn copy_assets(&self, dir: &Path) -> Result<(), MyError> {
+        for (rel_path, path) in &self.assets {
+            let target = dir.join(rel_path);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(path, target)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ThemesRequest {
+    location: PathBuf,
+}
+
+/// Names of the themes a compile of the book can use.
+pub fn get_themes(info: Json<ThemesRequest>) -> Result<impl Responder, MyError> {
+    Ok(HttpResponse::Ok().json(Theme::names(&info.location)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn builtin_themes_are_found() {
+        let temp_dir = TempDir::new("test_themes").unwrap();
+        let theme = Theme::find(temp_dir.path(), "manuscript").unwrap();
+        assert!(theme.typesetting.monospace);
+        assert_eq!(theme.css_with_file_urls(), theme.css);
+
+        let names = Theme::names(temp_dir.path()).unwrap();
+        assert_eq!(&names[..3], &["manuscript", "novel", "academic"]);
+        assert!(Theme::find(temp_dir.path(), "nope").is_err());
+        assert!(Theme::find(temp_dir.path(), "../novel").is_err());
+    }
+
+    #[test]
+    fn book_themes_have_assets_and_override_builtin_ones() {
+        let temp_dir = TempDir::new("test_themes").unwrap();
+        let location = temp_dir.path();
+        let dir = location.join(".collabook/themes/novel");
+        fs::create_dir_all(dir.join("fonts")).unwrap();
+        fs::write(
+            dir.join("theme.css"),
+            "@font-face { src: url('fonts/a.ttf'); }\nbody { background: url(data:x); }",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("theme.toml"),
+            "font_size = 10.0\njustify = false\n",
+        )
+        .unwrap();
+        fs::write(dir.join("fonts/a.ttf"), "font").unwrap();
+
+        let theme = Theme::find(location, "novel").unwrap();
+        assert_eq!(
+            theme.assets,
+            vec![("fonts/a.ttf".to_string(), dir.join("fonts/a.ttf"))]
+        );
+        assert_eq!(
+            theme.typesetting,
+            Typesetting {
+                font_size: 10.0,
+                justify: false,
+                ..Typesetting::default()
+            }
+        );
+        let css = theme.css_with_file_urls();
+        assert!(css.contains("url(\"file://"));
+        assert!(css.contains("/.collabook/themes/novel/fonts/a.ttf\")"));
+        assert!(css.contains("url(data:x)"));
+
+        let out = location.join("out");
+        theme.copy_assets(&out).unwrap();
+        assert!(out.join("fonts/a.ttf").is_file());
+        assert_eq!(
+            Theme::names(location)
+                .unwrap()
+                .iter()
+                .filter(|n| *n == "novel")
+                .count(),
+            1
+        );
+    }
+}
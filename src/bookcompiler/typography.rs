@@ -0,0 +1,294 @@
+use pulldown_cmark::{Event, Tag};
+
+const NBSP: char = '\u{a0}';
+const NARROW_NBSP: char = '\u{202f}';
+const APOSTROPHE: char = '\u{2019}';
+
+/// Quotation marks and spacing the typography stage of a compile follows.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Locale {
+    English,
+    /// Guillemets, and non-breaking spaces inside them and before `; : ! ?`
+    French,
+    German,
+}
+
+impl Locale {
+    /// The locale of a language code like `fr` or `de-CH`, English for any other language.
+    pub fn of_language(language: &str) -> Self {
+        let code = language
+            .split(|c| c == '-' || c == '_')
+            .next()
+            .unwrap_or("");
+        match code.to_lowercase().as_str() {
+            "fr" => Locale::French,
+            "de" => Locale::German,
+            _ => Locale::English,
+        }
+    }
+
+    // opening and closing double quotes followed by the single ones
+    fn quotes(self) -> [char; 4] {
+        match self {
+            Locale::English => ['\u{201c}', '\u{201d}', '\u{2018}', '\u{2019}'],
+            Locale::French => ['\u{ab}', '\u{bb}', '\u{201c}', '\u{201d}'],
+            Locale::German => ['\u{201e}', '\u{201c}', '\u{201a}', '\u{2018}'],
+        }
+    }
+}
+
+/// Turns straight quotes, `--`, `---` and `...` into curly quotes, en and em dashes and
+/// ellipses. Text is fed in the order it is read so quotes opened in one piece of text are
+/// closed in a later one.
+pub struct Typographer {
+    locale: Locale,
+    /// Last character written
+    prev: char,
+    double_open: bool,
+    single_open: bool,
+    /// Spaces typed after an opening guillemet are dropped, it gets a non-breaking one
+    after_guillemet: bool,
+}
+
+impl Typographer {
+    pub fn new(locale: Locale) -> Self {
+        Typographer {
+            locale,
+            prev: ' ',
+            double_open: false,
+            single_open: false,
+            after_guillemet: false,
+        }
+    }
+
+    /// Text that is kept as it is, like code, still decides whether the next quote opens.
+    pub fn verbatim(&mut self, text: &str) {
+        if let Some(c) = text.chars().last() {
+            //next to a quote, code reads like a word
+            self.prev = if c.is_whitespace() { ' ' } else { 'x' };
+            self.after_guillemet = false;
+        }
+    }
+
+    /// A new block or line starts.
+    pub fn space(&mut self) {
+        self.prev = ' ';
+    }
+
+    // a quote opens after a space and closes before one, when both or neither are true it
+    // closes the quote left open
+    fn opens(&self, next: Option<char>, open: bool) -> bool {
+        let quotes = self.locale.quotes();
+        let after_space = self.prev.is_whitespace()
+            || "([{\u{2013}\u{2014}".contains(self.prev)
+            || self.prev == quotes[0]
+            || self.prev == quotes[2];
+        let before_space = next.map_or(false, |n| n.is_whitespace() || ".,;:!?)]}".contains(n));
+        match (after_space, before_space) {
+            (true, false) => true,
+            (false, true) => false,
+            _ => !open,
+        }
+    }
+
+    fn push(&mut self, out: &mut String, c: char) {
+        if self.locale == Locale::French {
+            if c == ' ' && self.after_guillemet {
+                return;
+            }
+            let trimmed = out.trim_end_matches(' ').len();
+            let had_space = trimmed < out.len();
+            match c {
+                '\u{bb}' if had_space || !self.prev.is_whitespace() => {
+                    out.truncate(trimmed);
+                    out.push(NBSP);
+                }
+                //only spaces that were typed are replaced, `10:30` or urls stay as they are
+                ';' | '!' | '?' if had_space => {
+                    out.truncate(trimmed);
+                    out.push(NARROW_NBSP);
+                }
+                ':' if had_space => {
+                    out.truncate(trimmed);
+                    out.push(NBSP);
+                }
+                _ => {}
+            }
+        }
+        out.push(c);
+        self.prev = c;
+        self.after_guillemet = false;
+        if self.locale == Locale::French && c == '\u{ab}' {
+            out.push(NBSP);
+            self.prev = NBSP;
+            self.after_guillemet = true;
+        }
+    }
+
+    pub fn text(&mut self, text: &str) -> String {
+        let quotes = self.locale.quotes();
+        let chars: Vec<char> = text.chars().collect();
+        let mut out = String::with_capacity(text.len());
+        let mut i = 0;
+        while i < chars.len() {
+            let rest = &chars[i..];
+            let next = chars.get(i + 1).cloned();
+            let (c, len) = match rest[0] {
+                '.' if rest.starts_with(&['.', '.', '.']) => ('\u{2026}', 3),
+                '-' if rest.starts_with(&['-', '-', '-']) => ('\u{2014}', 3),
+                '-' if rest.starts_with(&['-', '-']) => ('\u{2013}', 2),
+                '"' => {
+                    self.double_open = self.opens(next, self.double_open);
+                    (
+                        if self.double_open {
+                            quotes[0]
+                        } else {
+                            quotes[1]
+                        },
+                        1,
+                    )
+                }
+                '\'' if self.prev.is_alphanumeric()
+                    && next.map_or(false, char::is_alphanumeric) =>
+                {
+                    (APOSTROPHE, 1)
+                }
+                //like in '90s
+                '\'' if next.map_or(false, |n| n.is_ascii_digit()) => (APOSTROPHE, 1),
+                '\'' => {
+                    if self.opens(next, self.single_open) {
+                        self.single_open = true;
+                        (quotes[2], 1)
+                    } else if self.single_open {
+                        self.single_open = false;
+                        (quotes[3], 1)
+                    } else {
+                        (APOSTROPHE, 1)
+                    }
+                }
+                c => (c, 1),
+            };
+            self.push(&mut out, c);
+            i += len;
+        }
+        out
+    }
+}
+
+/// Applies the typography of `locale` to the text of markdown events, code spans, code blocks
+/// and raw html are left untouched. Without a locale the events are passed on as they are.
+pub fn markdown_events<'a, I>(events: I, locale: Option<Locale>) -> impl Iterator<Item = Event<'a>>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    let mut typographer = locale.map(Typographer::new);
+    let mut code = 0;
+    events.map(move |event| {
+        let typographer = match typographer {
+            Some(ref mut typographer) => typographer,
+            None => return event,
+        };
+        match event {
+            Event::Start(tag) => {
+                match tag {
+                    Tag::Code | Tag::CodeBlock(_) => code += 1,
+                    Tag::Emphasis | Tag::Strong | Tag::Link(..) | Tag::Image(..) => {}
+                    _ => typographer.space(),
+                }
+                Event::Start(tag)
+            }
+            Event::End(tag) => {
+                if let Tag::Code | Tag::CodeBlock(_) = tag {
+                    code -= 1;
+                }
+                Event::End(tag)
+            }
+            Event::Text(text) => {
+                if code > 0 {
+                    typographer.verbatim(&text);
+                    Event::Text(text)
+                } else {
+                    Event::Text(typographer.text(&text).into())
+                }
+            }
+            Event::Html(html) => {
+                typographer.verbatim(&html);
+                Event::Html(html)
+            }
+            Event::InlineHtml(html) => {
+                typographer.verbatim(&html);
+                Event::InlineHtml(html)
+            }
+            Event::SoftBreak => {
+                typographer.space();
+                Event::SoftBreak
+            }
+            Event::HardBreak => {
+                typographer.space();
+                Event::HardBreak
+            }
+            event => event,
+        }
+    })
+}
+
+/// Applies the typography of `locale` to plain text.
+pub fn plain_text(content: &str, locale: Option<Locale>) -> String {
+    match locale {
+        Some(locale) => Typographer::new(locale).text(content),
+        None => content.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bookcompiler::render::markdown_to_html;
+
+    fn english(text: &str) -> String {
+        Typographer::new(Locale::English).text(text)
+    }
+
+    #[test]
+    fn quotes_dashes_and_ellipses() {
+        assert_eq!(
+            english("\"It's the '90s--no, wait...\" she said---'really'."),
+            "\u{201c}It\u{2019}s the \u{2019}90s\u{2013}no, wait\u{2026}\u{201d} she said\u{2014}\u{2018}really\u{2019}."
+        );
+        assert_eq!(english("the Joneses' dog"), "the Joneses\u{2019} dog");
+        assert_eq!(
+            Typographer::new(Locale::German).text("Er sagte \"Hallo 'du'\"."),
+            "Er sagte \u{201e}Hallo \u{201a}du\u{2018}\u{201c}."
+        );
+    }
+
+    #[test]
+    fn french_gets_guillemets_and_non_breaking_spaces() {
+        let mut typographer = Typographer::new(Locale::French);
+        assert_eq!(
+            typographer.text("Il dit \" Bonjour \" ; vraiment ? Oui : 10:30!"),
+            "Il dit \u{ab}\u{a0}Bonjour\u{a0}\u{bb}\u{202f}; vraiment\u{202f}? Oui\u{a0}: 10:30!"
+        );
+        assert_eq!(
+            typographer.text("\"Salut\""),
+            "\u{ab}\u{a0}Salut\u{a0}\u{bb}"
+        );
+        assert_eq!(Locale::of_language("fr-CA"), Locale::French);
+        assert_eq!(Locale::of_language("de_CH"), Locale::German);
+        assert_eq!(Locale::of_language("es"), Locale::English);
+    }
+
+    #[test]
+    fn code_and_verbatim_blocks_are_left_alone() {
+        let html = markdown_to_html(
+            "\"*Hi*\" -- `a -- \"b\"`'s\n\n    x = \"...\"\n",
+            Some(Locale::English),
+        );
+        assert_eq!(
+            html,
+            "<p>\u{201c}<em>Hi</em>\u{201d} \u{2013} <code>a -- &quot;b&quot;</code>\u{2019}s</p>\n\
+             <pre><code>x = &quot;...&quot;\n</code></pre>\n"
+        );
+        assert_eq!(markdown_to_html("\"a\"", None), "<p>&quot;a&quot;</p>\n");
+    }
+}
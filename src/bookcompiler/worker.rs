@@ -0,0 +1,293 @@
+use super::Layout;
+use crate::error::MyError;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Command line flag that turns the server binary into a renderer worker.
+pub const RENDER_WORKER_FLAG: &str = "--render-worker";
+/// Makes a worker only check that wkhtmltopdf can be loaded.
+const RENDER_PROBE_FLAG: &str = "--probe";
+
+/// How pdf rendering is supervised, read from the environment:
+/// `COLLABOOK_RENDER_WORKERS` compiles run at once (2),
+/// `COLLABOOK_RENDER_TIMEOUT` seconds before a worker is killed (300) and
+/// `COLLABOOK_RENDER_RETRIES` restarts of a crashed worker (1).
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderConfig {
+    pub workers: usize,
+    pub timeout: Duration,
+    pub retries: u32,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        RenderConfig {
+            workers: 2,
+            timeout: Duration::from_secs(300),
+            retries: 1,
+        }
+    }
+}
+
+fn env_var<T: std::str::FromStr>(name: &str) -> Option<T> {
+    std::env::var(name).ok().and_then(|v| v.trim().parse().ok())
+}
+
+impl RenderConfig {
+    pub fn from_env() -> Self {
+        let default = RenderConfig::default();
+        RenderConfig {
+            workers: env_var("COLLABOOK_RENDER_WORKERS")
+                .filter(|&n| n > 0)
+                .unwrap_or(default.workers),
+            timeout: env_var("COLLABOOK_RENDER_TIMEOUT")
+                .map(Duration::from_secs)
+                .unwrap_or(default.timeout),
+            retries: env_var("COLLABOOK_RENDER_RETRIES").unwrap_or(default.retries),
+        }
+    }
+}
+
+/// Everything a worker needs to render one pdf, sent as json on its stdin.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RenderTask {
+    pub html: String,
+    pub layout: Layout,
+    pub title: String,
+    /// Authors of the book for the header and footer
+    pub author: String,
+    pub output: PathBuf,
+}
+
+#[cfg(feature = "wkhtmltopdf")]
+fn render_in_worker(probe: bool) -> Result<(), MyError> {
+    let pdf_app = wkhtmltopdf::PdfApplication::new()?;
+    if probe {
+        return Ok(());
+    }
+    let mut input = String::new();
+    std::io::stdin().read_to_string(&mut input)?;
+    let task: RenderTask = serde_json::from_str(&input)?;
+
+    let mut builder = pdf_app.builder();
+    task.layout.apply(&mut builder, &task.title, &task.author);
+    let mut pdfout = builder.build_from_html(&task.html)?;
+    pdfout.save(&task.output)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "wkhtmltopdf"))]
+fn render_in_worker(_probe: bool) -> Result<(), MyError> {
+    Err("Collabook was built without wkhtmltopdf".into())
+}
+
+/// Entry point of a worker process, returns the exit code.
+pub fn run_render_worker() -> i32 {
+    let probe = std::env::args().any(|arg| arg == RENDER_PROBE_FLAG);
+    match render_in_worker(probe) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+enum Outcome {
+    Exited(ExitStatus, String),
+    TimedOut,
+    Stopped,
+}
+
+// stdin and stderr are handled on their own threads, a stuck worker can't block on a full pipe
+fn supervise<F: Fn() -> bool>(
+    mut child: Child,
+    input: &[u8],
+    timeout: Duration,
+    should_stop: &F,
+) -> Result<Outcome, MyError> {
+    let stderr = child.stderr.take().map(|mut stderr| {
+        thread::spawn(move || {
+            let mut out = String::new();
+            let _ = stderr.read_to_string(&mut out);
+            out
+        })
+    });
+    if let Some(mut stdin) = child.stdin.take() {
+        let input = input.to_vec();
+        //a worker that dies early closes the pipe, its exit status tells what happened
+        thread::spawn(move || stdin.write_all(&input));
+    }
+
+    let started = Instant::now();
+    let outcome = loop {
+        if let Some(status) = child.try_wait()? {
+            let stderr = stderr
+                .and_then(|handle| handle.join().ok())
+                .unwrap_or_default();
+            break Outcome::Exited(status, stderr);
+        }
+        if should_stop() {
+            break Outcome::Stopped;
+        }
+        if started.elapsed() > timeout {
+            break Outcome::TimedOut;
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+
+    match outcome {
+        Outcome::Exited(..) => {}
+        _ => {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+    Ok(outcome)
+}
+
+/// Runs `command` with `input` on stdin until it exits. It is killed after the timeout or as
+/// soon as `should_stop` returns true, and restarted when it crashes.
+pub fn run_supervised<F: Fn() -> Command, S: Fn() -> bool>(
+    config: &RenderConfig,
+    command: F,
+    input: &[u8],
+    should_stop: S,
+) -> Result<(), MyError> {
+    let mut attempt = 0;
+    loop {
+        let child = command()
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| MyError(format!("Couldn't start renderer: {}", e)))?;
+
+        match supervise(child, input, config.timeout, &should_stop)? {
+            Outcome::Exited(status, _) if status.success() => return Ok(()),
+            Outcome::Exited(status, stderr) => {
+                if attempt >= config.retries {
+                    let reason = stderr.trim();
+                    let reason = if reason.is_empty() {
+                        status.to_string()
+                    } else {
+                        reason.to_string()
+                    };
+                    return Err(MyError(format!("Renderer failed: {}", reason)));
+                }
+                warn!("renderer failed with {}, restarting", status);
+                attempt += 1;
+            }
+            Outcome::TimedOut => {
+                return Err(MyError(format!(
+                    "Renderer timed out after {} seconds",
+                    config.timeout.as_secs()
+                )))
+            }
+            Outcome::Stopped => return Err("Compile was cancelled".into()),
+        }
+    }
+}
+
+fn worker_command(args: &[&str]) -> Result<impl Fn() -> Command, MyError> {
+    let exe = std::env::current_exe()?;
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    Ok(move || {
+        let mut command = Command::new(&exe);
+        command.args(&args);
+        command
+    })
+}
+
+/// Renders `task` in a worker process started from the server binary.
+pub fn render_pdf<S: Fn() -> bool>(
+    config: &RenderConfig,
+    task: &RenderTask,
+    should_stop: S,
+) -> Result<(), MyError> {
+    let input = serde_json::to_vec(task)?;
+    run_supervised(
+        config,
+        worker_command(&[RENDER_WORKER_FLAG])?,
+        &input,
+        should_stop,
+    )
+}
+
+/// Checks in a worker process that wkhtmltopdf can be loaded on this machine.
+pub fn probe(config: &RenderConfig) -> Result<(), MyError> {
+    let config = RenderConfig {
+        retries: 0,
+        ..config.clone()
+    };
+    run_supervised(
+        &config,
+        worker_command(&[RENDER_WORKER_FLAG, RENDER_PROBE_FLAG])?,
+        &[],
+        || false,
+    )
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn sh(script: &'static str) -> impl Fn() -> Command {
+        move || {
+            let mut command = Command::new("sh");
+            command.arg("-c").arg(script);
+            command
+        }
+    }
+
+    fn config(timeout: u64, retries: u32) -> RenderConfig {
+        RenderConfig {
+            workers: 1,
+            timeout: Duration::from_millis(timeout),
+            retries,
+        }
+    }
+
+    #[test]
+    fn worker_gets_input_and_errors_are_reported() {
+        let ok = run_supervised(
+            &config(5000, 0),
+            sh("test \"$(cat)\" = hello"),
+            b"hello",
+            || false,
+        );
+        assert!(ok.is_ok());
+
+        let err = run_supervised(
+            &config(5000, 0),
+            sh("cat > /dev/null; echo broken >&2; exit 3"),
+            b"",
+            || false,
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Renderer failed: broken");
+    }
+
+    #[test]
+    fn hanging_worker_is_killed() {
+        let started = Instant::now();
+        let err = run_supervised(&config(200, 3), sh("sleep 10"), b"", || false).unwrap_err();
+        assert!(err.to_string().starts_with("Renderer timed out"));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn crashed_worker_is_restarted() {
+        let starts = Cell::new(0);
+        let command = || {
+            starts.set(starts.get() + 1);
+            sh("exit 1")()
+        };
+        assert!(run_supervised(&config(5000, 2), command, b"", || false).is_err());
+        assert_eq!(starts.get(), 3);
+    }
+}
@@ -93,8 +93,21 @@ impl cmp::PartialEq for MyError {
     }
 }
 
+#[cfg(feature = "wkhtmltopdf")]
 impl From<wkhtmltopdf::Error> for MyError {
     fn from(e: wkhtmltopdf::Error) -> MyError {
         MyError(e.to_string())
     }
 }
+
+impl From<zip::result::ZipError> for MyError {
+    fn from(e: zip::result::ZipError) -> MyError {
+        MyError(e.to_string())
+    }
+}
+
+impl From<printpdf::Error> for MyError {
+    fn from(e: printpdf::Error) -> MyError {
+        MyError(e.to_string())
+    }
+}
@@ -1,7 +1,8 @@
-use crate::book::Author;
+use crate::book::{Author, BookMeta};
 use crate::error::MyError;
 //use crate::vcs;
 use actix_web::{HttpResponse, Json, Responder};
+use std::path::PathBuf;
 
 pub trait HttpSend {
     fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response, MyError>;
@@ -125,14 +126,22 @@ impl<A: AccessToken, S: HttpSend> GithubClient<A, S> {
 pub struct CreateRepoRequest {
     name: String,
     description: Option<String>,
+    //description is taken from book metadata if not given
+    #[serde(default)]
+    location: Option<PathBuf>,
 }
 
 pub fn github_create_repo_request(
     info: Json<CreateRepoRequest>,
 ) -> Result<impl Responder, MyError> {
     let author = Author::read_from_disk()?;
+    let description = match (&info.description, &info.location) {
+        (Some(description), _) => Some(description.clone()),
+        (None, Some(location)) => BookMeta::read(location)?.description,
+        (None, None) => None,
+    };
     let _ = GithubClient::new(author)
-        .create_repo(&info.name, info.description.as_ref().map(String::as_ref))?;
+        .create_repo(&info.name, description.as_ref().map(String::as_ref))?;
     Ok(HttpResponse::Ok())
 }
 
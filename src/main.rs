@@ -9,11 +9,13 @@ mod bookcompiler;
 mod error;
 mod github;
 mod macros;
+mod template;
 mod vcs;
 
 use crate::book::*;
 use crate::bookcompiler::*;
 use crate::github::*;
+use crate::template::*;
 use crate::vcs::*;
 use actix::prelude::*;
 use actix_web::middleware::{cors::Cors, Logger};
@@ -23,17 +25,29 @@ use actix_web::{http, server, App};
 
 // websockets might be a better idea
 fn main() {
+    if std::env::args().any(|arg| arg == RENDER_WORKER_FLAG) {
+        std::process::exit(run_render_worker());
+    }
+
     std::env::set_var("RUST_LOG", "actix_web=info");
     env_logger::init();
 
     let _sys = actix::System::new("collabook-core");
-    let addr = SyncArbiter::start(1, || BookCompiler {
-        pdf_app: wkhtmltopdf::PdfApplication::new().unwrap(),
+    let jobs = Jobs::default();
+    let compiler_jobs = jobs.clone();
+    let render = RenderConfig::from_env();
+    let renderers = Renderers::detect(&render);
+    let compiler_renderers = renderers.clone();
+    let addr = SyncArbiter::start(render.workers, move || BookCompiler {
+        jobs: compiler_jobs.clone(),
+        renderers: compiler_renderers.clone(),
     });
 
     server::new(move || {
         App::with_state(AppState {
             compiler: addr.clone(),
+            jobs: jobs.clone(),
+            renderers: renderers.clone(),
         })
         .middleware(Logger::default())
         .configure(|app| {
@@ -45,6 +59,9 @@ fn main() {
                     r.method(http::Method::GET).f(book::get_author);
                     r.method(http::Method::POST).with(create_author);
                 })
+                .resource("/templates", |r| {
+                    r.method(http::Method::GET).f(get_templates)
+                })
                 .resource("/newbook", |r| {
                     r.method(http::Method::POST)
                         .with(new_book::<std::path::PathBuf>)
@@ -52,6 +69,9 @@ fn main() {
                 .resource("/openbook", |r| {
                     r.method(http::Method::POST).with(open_book)
                 })
+                .resource("/savebookmeta", |r| {
+                    r.method(http::Method::POST).with(save_book_meta)
+                })
                 .resource("/newfile", |r| r.method(http::Method::POST).with(new_file))
                 .resource("/savefile", |r| {
                     r.method(http::Method::POST).with(save_file)
@@ -59,6 +79,10 @@ fn main() {
                 .resource("/deletefile", |r| {
                     r.method(http::Method::POST).with(delete_file)
                 })
+                .resource("/movefile", |r| {
+                    r.method(http::Method::POST).with(move_file)
+                })
+                .resource("/reorder", |r| r.method(http::Method::POST).with(reorder))
                 .resource("/savesynopsis", |r| {
                     r.method(http::Method::POST).with(save_synopsis)
                 })
@@ -119,6 +143,40 @@ fn main() {
                 .resource("/compile", |r| {
                     r.method(http::Method::POST).with(compile_book)
                 })
+                .resource("/capabilities", |r| {
+                    r.method(http::Method::GET).f(get_capabilities)
+                })
+                .resource("/compilehistory", |r| {
+                    r.method(http::Method::POST).with(get_compile_history)
+                })
+                .resource("/downloadbuild", |r| {
+                    r.method(http::Method::POST).with(download_build)
+                })
+                .resource("/themes", |r| r.method(http::Method::POST).with(get_themes))
+                .resource("/clearcompilecache", |r| {
+                    r.method(http::Method::POST).with(clear_compile_cache)
+                })
+                .resource("/compilejobs", |r| {
+                    r.method(http::Method::GET).f(get_compile_jobs)
+                })
+                .resource("/compilejob", |r| {
+                    r.method(http::Method::POST).with(get_compile_job)
+                })
+                .resource("/cancelcompile", |r| {
+                    r.method(http::Method::POST).with(cancel_compile_job)
+                })
+                .resource("/compileartifact", |r| {
+                    r.method(http::Method::POST).with(get_compile_artifact)
+                })
+                .resource("/compilepresets", |r| {
+                    r.method(http::Method::POST).with(get_compile_presets)
+                })
+                .resource("/savecompilepreset", |r| {
+                    r.method(http::Method::POST).with(save_compile_preset)
+                })
+                .resource("/runcompilepreset", |r| {
+                    r.method(http::Method::POST).with(run_compile_preset)
+                })
                 .register()
         })
     })
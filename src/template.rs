@@ -0,0 +1,276 @@
+use crate::book::{config_dir, Genre};
+use crate::error::MyError;
+use actix_web::{HttpRequest, HttpResponse, Responder};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+use walkdir::WalkDir;
+
+/// A file or folder created by a template, `rel_path` is relative to the book root and always
+/// uses `/` as separator. Parents are listed before their children.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateEntry {
+    pub rel_path: String,
+    pub is_folder: bool,
+    pub synopsis: String,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pub name: String,
+    pub entries: Vec<TemplateEntry>,
+}
+
+// (rel_path, is_folder, synopsis, content)
+type BuiltinEntry = (&'static str, bool, &'static str, &'static str);
+
+const FICTION: &[BuiltinEntry] = &[
+    ("Book", true, "", ""),
+    ("Book/Chap1", true, "", ""),
+    ("Book/Chap1/Sec1", false, "", ""),
+    ("Research", true, "", ""),
+    ("Research/Chars", false, "Characters of the story", ""),
+    (
+        "Research/World",
+        false,
+        "Places and setting of the story",
+        "",
+    ),
+];
+
+const FANTASY: &[BuiltinEntry] = &[
+    ("Book", true, "", ""),
+    ("Book/Chap1", true, "", ""),
+    ("Book/Chap1/Sec1", false, "", ""),
+    ("Research", true, "", ""),
+    ("Research/Chars", false, "Characters of the story", ""),
+    (
+        "Research/World",
+        false,
+        "Places and setting of the story",
+        "",
+    ),
+    ("Research/Maps", true, "Maps of the world", ""),
+    (
+        "Research/Magic System",
+        false,
+        "Rules, costs and limits of magic",
+        "# Source\n\n# Rules\n\n# Costs\n\n# Limits\n",
+    ),
+    (
+        "Research/Glossary",
+        false,
+        "Invented words, names and their meanings",
+        "",
+    ),
+];
+
+const ACADEMIC: &[BuiltinEntry] = &[
+    ("Book", true, "", ""),
+    (
+        "Book/Abstract",
+        false,
+        "Summary of the problem, method and results",
+        "",
+    ),
+    ("Book/Chapters", true, "", ""),
+    ("Book/Chapters/Chap1", true, "Introduction", ""),
+    ("Book/Chapters/Chap1/Sec1", false, "", ""),
+    ("Book/Bibliography", false, "Works cited", ""),
+    ("Research", true, "", ""),
+    ("Research/Notes", false, "", ""),
+    ("Research/Sources", false, "Papers and books to read", ""),
+];
+
+const BUILTIN: &[(&str, &[BuiltinEntry])] = &[
+    ("fiction", FICTION),
+    ("fantasy", FANTASY),
+    ("academic", ACADEMIC),
+];
+
+fn user_templates_dir() -> Result<std::path::PathBuf, MyError> {
+    Ok(config_dir()?.join("templates"))
+}
+
+impl Template {
+    fn builtin(name: &str) -> Option<Self> {
+        BUILTIN
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(n, entries)| Template {
+                name: n.to_string(),
+                entries: entries
+                    .iter()
+                    .map(|(rel_path, is_folder, synopsis, content)| TemplateEntry {
+                        rel_path: rel_path.to_string(),
+                        is_folder: *is_folder,
+                        synopsis: synopsis.to_string(),
+                        content: content.to_string(),
+                    })
+                    .collect(),
+            })
+    }
+
+    pub fn for_genre(genre: &Genre) -> Self {
+        let name = match genre {
+            Genre::Fantasy => "fantasy",
+            Genre::Fiction => "fiction",
+            Genre::Academic => "academic",
+        };
+        Template::builtin(name).expect("every genre has a builtin template")
+    }
+
+    /// Reads a user template. Every file and folder inside `dir` is copied into the new book,
+    /// the synopsis of `dir/a/b` is read from `dir/.synopsis/a/b` if it exists.
+    pub fn from_dir(name: &str, dir: &Path) -> Result<Self, MyError> {
+        let mut entries = Vec::new();
+        for entry in WalkDir::new(dir)
+            .min_depth(1)
+            .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+            .into_iter()
+            .filter_entry(|e| {
+                !e.file_name()
+                    .to_str()
+                    .map(|s| s.starts_with('.'))
+                    .unwrap_or(false)
+            })
+        {
+            let entry = entry.map_err(|e| e.to_string())?;
+            let rel_path = entry.path().strip_prefix(dir)?;
+            let is_folder = entry.file_type().is_dir();
+
+            let synopsis_path = dir.join(".synopsis").join(rel_path);
+            let synopsis = if synopsis_path.is_file() {
+                fs::read_to_string(synopsis_path)?
+            } else {
+                String::new()
+            };
+            let content = if is_folder {
+                String::new()
+            } else {
+                fs::read_to_string(entry.path())?
+            };
+
+            entries.push(TemplateEntry {
+                rel_path: rel_path
+                    .to_str()
+                    .ok_or("Filename contains invalid utf-8")?
+                    .replace("\\", "/"),
+                is_folder,
+                synopsis,
+                content,
+            });
+        }
+        Ok(Template {
+            name: name.to_string(),
+            entries,
+        })
+    }
+
+    /// User templates in `<config dir>/templates/<name>` take precedence over builtin ones.
+    pub fn find(name: &str) -> Result<Self, MyError> {
+        if Path::new(name).file_name() != Some(OsStr::new(name)) {
+            Err(MyError(format!("Template {} not found", name)))?
+        }
+        let dir = user_templates_dir()?.join(name);
+        if dir.is_dir() {
+            return Template::from_dir(name, &dir);
+        }
+        Template::builtin(name).ok_or_else(|| MyError(format!("Template {} not found", name)))
+    }
+
+    pub fn names() -> Result<Vec<String>, MyError> {
+        let mut names: Vec<String> = BUILTIN.iter().map(|(n, _)| n.to_string()).collect();
+        let dir = user_templates_dir()?;
+        if dir.is_dir() {
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                if entry.file_type()?.is_dir() {
+                    if let Some(name) = entry.file_name().to_str() {
+                        if !names.iter().any(|n| n == name) {
+                            names.push(name.to_string());
+                        }
+                    }
+                }
+            }
+        }
+        Ok(names)
+    }
+}
+
+pub fn get_templates(_req: &HttpRequest<crate::AppState>) -> Result<impl Responder, MyError> {
+    Ok(HttpResponse::Ok().json(Template::names()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn builtin_templates_list_parents_first() {
+        for (name, _) in BUILTIN {
+            let template = Template::builtin(name).unwrap();
+            for (i, entry) in template.entries.iter().enumerate() {
+                if let Some(sep) = entry.rel_path.rfind('/') {
+                    let parent = &entry.rel_path[..sep];
+                    assert!(template.entries[..i]
+                        .iter()
+                        .any(|e| e.rel_path == parent && e.is_folder));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn template_names_must_not_leave_the_templates_dir() {
+        assert!(Template::find("fiction").is_ok());
+        assert!(Template::find("../x").is_err());
+        assert!(Template::find("..").is_err());
+        assert!(Template::find("x/../../..").is_err());
+    }
+
+    #[test]
+    fn user_templates_come_from_the_config_dir() {
+        let dir = user_templates_dir().unwrap().join("fiction");
+        fs::create_dir_all(dir.join("Scenes")).unwrap();
+        fs::write(dir.join("Scenes/Opening"), "").unwrap();
+        fs::create_dir_all(user_templates_dir().unwrap().join("screenplay")).unwrap();
+
+        let fiction = Template::find("fiction").unwrap();
+        let paths: Vec<&str> = fiction
+            .entries
+            .iter()
+            .map(|e| e.rel_path.as_str())
+            .collect();
+        assert_eq!(paths, vec!["Scenes", "Scenes/Opening"]);
+        assert_eq!(
+            Template::names().unwrap(),
+            vec!["fiction", "fantasy", "academic", "screenplay"]
+        );
+    }
+
+    #[test]
+    fn template_from_dir() {
+        let temp_dir = TempDir::new("test_template").unwrap();
+        let path = temp_dir.path();
+        fs::create_dir_all(path.join("Book/Act 1")).unwrap();
+        fs::write(path.join("Book/Act 1/Opening"), "It was a dark night").unwrap();
+        fs::create_dir_all(path.join(".synopsis/Book/Act 1")).unwrap();
+        fs::write(path.join(".synopsis/Book/Act 1/Opening"), "Hook").unwrap();
+
+        let template = Template::from_dir("screenplay", path).unwrap();
+        let paths: Vec<&str> = template
+            .entries
+            .iter()
+            .map(|e| e.rel_path.as_str())
+            .collect();
+        assert_eq!(paths, vec!["Book", "Book/Act 1", "Book/Act 1/Opening"]);
+
+        let opening = &template.entries[2];
+        assert_eq!(opening.is_folder, false);
+        assert_eq!(opening.content, "It was a dark night");
+        assert_eq!(opening.synopsis, "Hook");
+    }
+}
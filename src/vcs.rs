@@ -1,9 +1,10 @@
 use actix_web::{HttpResponse, Json, Responder, Result};
 use chrono::prelude::*;
 use git2::{
-    build::CheckoutBuilder, Branch, BranchType, Index, IndexAddOption, Oid, PushOptions, Remote,
-    RemoteCallbacks, Repository,
+    build::CheckoutBuilder, Branch, BranchType, Index, IndexAddOption, ObjectType, Oid,
+    PushOptions, Remote, RemoteCallbacks, Repository, Tree,
 };
+use std::fs;
 use std::ops::Deref;
 use std::path;
 use std::path::Path;
@@ -56,6 +57,57 @@ impl BookRepo {
         Ok(index)
     }
 
+    // `$ git mv` for already tracked paths, `from` and `to` are relative to the workdir.
+    // _add_all never removes entries so without this a move shows up as a copy in history.
+    pub fn _stage_move<P: AsRef<Path>>(&self, from: &[P], to: &[P]) -> Result<(), MyError> {
+        let pathspec = |paths: &[P]| -> Vec<String> {
+            paths
+                .iter()
+                .map(|p| p.as_ref().to_string_lossy().replace("\\", "/"))
+                .collect()
+        };
+
+        let mut index = self.index()?;
+        index.remove_all(pathspec(from).iter(), None)?;
+        index.add_all(pathspec(to).iter(), IndexAddOption::DEFAULT, None)?;
+        index.write()?;
+        Ok(())
+    }
+
+    /// Pairs every path of `removed` with the path of `added` that has the same contents as
+    /// the staged file, so files renamed or moved outside of collabook can be told apart from
+    /// new ones. Paths are relative to the workdir, files which were never staged aren't found.
+    pub fn renamed_files(
+        &self,
+        removed: &[PathBuf],
+        added: &[PathBuf],
+    ) -> Result<Vec<(PathBuf, PathBuf)>, MyError> {
+        let workdir = self
+            .workdir()
+            .ok_or("Book repository has no working directory")?;
+        let index = self.index()?;
+        let mut added: Vec<(Oid, &PathBuf)> = added
+            .iter()
+            .filter_map(|path| {
+                Oid::hash_file(ObjectType::Blob, workdir.join(path))
+                    .ok()
+                    .map(|oid| (oid, path))
+            })
+            .collect();
+
+        let mut renamed = Vec::new();
+        for from in removed {
+            let staged = match index.get_path(from, 0) {
+                Some(entry) => entry.id,
+                None => continue,
+            };
+            if let Some(i) = added.iter().position(|(oid, _)| *oid == staged) {
+                renamed.push((from.clone(), added.remove(i).1.clone()));
+            }
+        }
+        Ok(renamed)
+    }
+
     fn _commit<S: AsRef<str>>(&self, msg: S, author: &Author) -> Result<Oid, MyError> {
         let mut index = self._add_all()?;
         let sig = git2::Signature::now(&author.name, &author.email)?;
@@ -177,6 +229,47 @@ impl BookRepo {
         Ok(())
     }
 
+    /// Commit a branch, tag or commit id like `draft-2` or `HEAD~1` points to.
+    pub fn revision_commit(&self, revision: &str) -> Result<Oid, MyError> {
+        Ok(self.revparse_single(revision)?.peel_to_commit()?.id())
+    }
+
+    /// Year `oid` was committed in, in the time zone of the committer.
+    pub fn commit_year(&self, oid: Oid) -> Result<i32, MyError> {
+        let time = self.find_commit(oid)?.time();
+        let naive_datetime = NaiveDateTime::from_timestamp(
+            time.seconds() + i64::from(time.offset_minutes()) * 60,
+            0,
+        );
+        Ok(naive_datetime.year())
+    }
+
+    /// Writes the files of `revision` into `dir` straight from the object database, the checkout
+    /// and index are left alone. Returns the commit that was exported.
+    pub fn export_revision(&self, revision: &str, dir: &Path) -> Result<Oid, MyError> {
+        let oid = self.revision_commit(revision)?;
+        fs::create_dir_all(dir)?;
+        self.export_tree(&self.find_commit(oid)?.tree()?, dir)?;
+        Ok(oid)
+    }
+
+    fn export_tree(&self, tree: &Tree, dir: &Path) -> Result<(), MyError> {
+        for entry in tree.iter() {
+            let name = entry.name().ok_or("Filename contains invalid utf-8")?;
+            let path = dir.join(name);
+            match entry.kind() {
+                Some(ObjectType::Tree) => {
+                    fs::create_dir_all(&path)?;
+                    self.export_tree(&self.find_tree(entry.id())?, &path)?;
+                }
+                Some(ObjectType::Blob) => fs::write(&path, self.find_blob(entry.id())?.content())?,
+                //submodules aren't part of the book
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
     // `$ git rebase dev` here branch is the current branch and dev is the upstream branch.
     fn _rebase(&self, branch: &Branch, upstream: &Branch) -> Result<(), MyError> {
         let branch_annotated_commit = self.reference_to_annotated_commit(branch.get())?;
@@ -226,7 +319,7 @@ impl BookRepo {
 
         //TODO: not sure why this is needed should probably use author config data here
         let random_sig = git2::Signature::now("rebaseauthor", "rebasemail")?;
-        rebase.finish(&random_sig)?;;
+        rebase.finish(&random_sig)?;
         Ok(())
     }
 
@@ -519,6 +612,24 @@ mod tests {
         assert_eq!(commit.message().unwrap(), "test commit");
     }
 
+    #[test]
+    fn test_commit_year() {
+        let temp_dir = TempDir::new("test_dir").unwrap();
+        let repo = BookRepo::new(temp_dir.path()).unwrap();
+
+        //2018-12-31 23:30 UTC is already 2019 for a committer two hours ahead
+        let time = git2::Time::new(1_546_299_000, 120);
+        let signature = git2::Signature::new("name", "email", &time).unwrap();
+        let tree = repo
+            .find_tree(repo.index().unwrap().write_tree().unwrap())
+            .unwrap();
+        let oid = repo
+            .commit(Some("HEAD"), &signature, &signature, "draft", &tree, &[])
+            .unwrap();
+
+        assert_eq!(repo.commit_year(oid).unwrap(), 2019);
+    }
+
     #[test]
     fn test_create_branch() {
         let temp_dir = TempDir::new("test_dir").unwrap();